@@ -8,6 +8,8 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+// Deliberately recursive, so there is a cycle in the graph.
+#[allow(unconditional_recursion)]
 fn foo() {
     println!("Hello World!");
     foo();
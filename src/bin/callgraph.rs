@@ -12,10 +12,171 @@
 
 extern crate callgraph;
 
+use callgraph::Config;
+
+use std::env;
+use std::process::{self, Command};
+
+const USAGE: &str = "\
+Usage: callgraph [OPTIONS] -- RUSTC_ARGS...
+       callgraph RUSTC_ARGS...
+       callgraph [OPTIONS] --load FILE...
+       callgraph callers|callees PATTERN [OPTIONS] -- RUSTC_ARGS...
+       callgraph callers|callees PATTERN [OPTIONS] --load FILE...
+       callgraph dead-code [OPTIONS] -- RUSTC_ARGS...
+       callgraph recursion [OPTIONS] -- RUSTC_ARGS...
+       callgraph unsafe [OPTIONS] -- RUSTC_ARGS...
+       callgraph panics [OPTIONS] -- RUSTC_ARGS...
+       callgraph no-alloc [OPTIONS] -- RUSTC_ARGS...
+       callgraph path FROM TO [OPTIONS] -- RUSTC_ARGS...
+       callgraph diff OLD NEW [OPTIONS]
+
+Subcommands:
+    callers PATTERN        only output the functions matching PATTERN and the
+                           functions which (transitively) call them
+    callees PATTERN        only output the functions matching PATTERN and the
+                           functions which they (transitively) call
+    dead-code              list the functions which can't be reached from the
+                           roots, rather than writing a graph
+    recursion              list direct and mutual recursion, with the calls
+                           involved, rather than writing a graph
+    unsafe                 list the safe exported functions which can reach
+                           unsafe code, each with a path to it
+    panics                 list the functions which can panic, each with a
+                           path to where std panics
+    no-alloc               list the functions marked
+                           `#[callgraph::no_alloc]` which can allocate,
+                           each with a path to where std allocates
+    path FROM TO           list the shortest path of calls from a function
+                           matching FROM to one matching TO
+    diff OLD NEW           compare two graphs saved with `--format json`,
+                           additions are green and removals red (or with
+                           `--format text`, a list of the changes)
+
+PATTERN, FROM, and TO are matched against qualified function names, `*`
+matches anything. A pattern without a `*` also matches names ending in
+`::PATTERN`. A pattern starting with `re:` is a regex instead.
+
+Options:
+    -o, --output PATH      write the graph to PATH ('-' for stdout)
+                           [default: <crate_name>-<kind>.<format>, where kind
+                           is lib, bin, proc-macro, or test]
+    --output-dir DIR       directory for the default output path
+    -f, --format FORMAT    output format: dot, text, or json [default: dot]
+    --load FILE            read a graph saved with `--format json` instead of
+                           running the compiler; several are merged
+    --skip-unconnected     drop functions which have no calls to or from them
+    --collapse-external    show each other crate as a single node
+    --include PATTERN      only show functions matching PATTERN, can be
+                           repeated
+    --exclude PATTERN      don't show functions matching PATTERN, can be
+                           repeated
+    --keep-boundary        with --include or --exclude, also show functions
+                           which call or are called by the functions shown
+    --aggregate LEVEL      show a node per module (LEVEL is `module`), or per
+                           impl and module (`impl`), rather than per function
+    --cluster-modules      draw the functions of each module in a cluster
+    --dump                 also dump the collected information to stdout
+    --mono                 graph monomorphised instances reachable from main
+                           and exported functions
+    --cluster-sccs         draw each set of mutually recursive functions
+                           (and each directly recursive function) in a cluster
+    --depth N              with callers or callees, follow at most N calls
+    --definite-only        with callers, callees, path, unsafe, panics, or
+                           no-alloc, ignore potential calls
+    --shortest K           with path, list the K shortest paths [default: 1]
+    --all N                with path, list all paths of at most N calls
+    --roots LIST           with dead-code, the roots to start from, a comma
+                           separated list of main, pub, test, exports, and
+                           trait-impls [default: all of them]
+    --list FILE            with no-alloc, also check the functions matching
+                           the patterns in FILE, one per line
+    -h, --help             print this message
+";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("error: {}\n\n{}", msg, USAGE);
+    process::exit(1);
+}
+
+// Splits the command line into our config and the arguments for rustc. Our
+// options come before a `--`; if there is no `--`, everything goes to rustc,
+// unless we are loading saved graphs (or diffing them), in which case there is
+// nothing for rustc.
+fn parse_args(args: Vec<String>) -> (Config, Vec<String>) {
+    let mut rustc_args = vec![args[0].clone()];
+
+    // Even without a `--`, `callgraph --help` is for us, not rustc.
+    let sep = args.iter().position(|a| a == "--");
+    if args[1..sep.unwrap_or(args.len())].iter().any(|a| a == "-h" || a == "--help") {
+        print!("{}", USAGE);
+        process::exit(0);
+    }
+
+    let saved = args.iter().any(|a| a == "--load") || args.get(1).map_or(false, |a| a == "diff");
+    let sep = match sep {
+        Some(sep) => sep,
+        None if saved => args.len(),
+        None => {
+            rustc_args.extend(args.into_iter().skip(1));
+            return (Config::default(), rustc_args);
+        }
+    };
+
+    let opts = &args[1..sep];
+    let config = Config::parse(opts).unwrap_or_else(|e| usage_error(&e));
+
+    rustc_args.extend(args.into_iter().skip(sep + 1));
+    (config, rustc_args)
+}
+
+// Cargo runs us as `callgraph RUSTC RUSTC_ARGS...` when we are the
+// RUSTC_WORKSPACE_WRAPPER set up by cargo-callgraph. opts are the options
+// passed to cargo-callgraph.
+fn run_as_wrapper(opts: &str, args: Vec<String>) -> i32 {
+    let rustc = &args[1];
+    let rustc_args = &args[2..];
+
+    // Cargo also uses the wrapper to query rustc (e.g., `rustc -vV`) and to
+    // build build scripts, those just go straight to rustc.
+    let is_build_script = rustc_args.windows(2)
+        .any(|w| w[0] == "--crate-name" && w[1].starts_with("build_script_"));
+    if env::var_os("CARGO_PRIMARY_PACKAGE").is_none() || is_build_script {
+        return Command::new(rustc)
+            .args(rustc_args)
+            .status()
+            .ok()
+            .and_then(|s| s.code())
+            .unwrap_or(1);
+    }
+
+    let opts: Vec<String> = opts.split(callgraph::WRAPPER_ARGS_SEP)
+        .filter(|o| !o.is_empty())
+        .map(|o| o.to_owned())
+        .collect();
+    let mut config = Config::parse(&opts).unwrap_or_else(|e| usage_error(&e));
+    config.stop_after_analysis = false;
+    config.env_deps.push(callgraph::WRAPPER_RUN_ENV.to_owned());
+
+    let mut driver_args = vec![args[0].clone()];
+    driver_args.extend(rustc_args.iter().cloned());
+    callgraph::run(config, driver_args)
+}
+
 fn main() {
-    let args: Vec<_> = std::env::args().collect();
+    let args: Vec<_> = env::args().collect();
+
+    if let Ok(opts) = env::var(callgraph::WRAPPER_ARGS_ENV) {
+        process::exit(run_as_wrapper(&opts, args));
+    }
 
-    callgraph::run(args);
+    let (config, rustc_args) = parse_args(args);
 
-    std::process::exit(0);
+    if config.diff.is_some() {
+        process::exit(callgraph::run_diff(config));
+    }
+    if !config.load.is_empty() {
+        process::exit(callgraph::run_saved(config));
+    }
+    process::exit(callgraph::run(config, rustc_args));
 }
@@ -0,0 +1,191 @@
+// `cargo callgraph`: runs `cargo check` with the callgraph binary as the rustc
+// wrapper for workspace members, so each of them is analysed with exactly the
+// command line Cargo computes for it. Dependencies are compiled as usual.
+
+#![cfg(not(test))]
+
+extern crate callgraph;
+
+use callgraph::Config;
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+Usage: cargo callgraph [OPTIONS] [-- CARGO_CHECK_ARGS...]
+       cargo callgraph callers|callees PATTERN [OPTIONS] [-- CARGO_CHECK_ARGS...]
+       cargo callgraph dead-code [OPTIONS] [-- CARGO_CHECK_ARGS...]
+       cargo callgraph recursion [OPTIONS] [-- CARGO_CHECK_ARGS...]
+       cargo callgraph unsafe [OPTIONS] [-- CARGO_CHECK_ARGS...]
+       cargo callgraph panics [OPTIONS] [-- CARGO_CHECK_ARGS...]
+       cargo callgraph no-alloc [OPTIONS] [-- CARGO_CHECK_ARGS...]
+       cargo callgraph path FROM TO [OPTIONS] [-- CARGO_CHECK_ARGS...]
+       cargo callgraph diff OLD NEW [OPTIONS]
+
+Writes a callgraph for each crate in the workspace, or with --merge, a single
+graph for the whole workspace. The workspace's crates are checked again on
+every run, even if Cargo considers them fresh.
+
+The callers and callees subcommands only output the functions matching
+PATTERN and their transitive callers or callees, see `callgraph --help`.
+dead-code lists the functions of each crate (or, with --merge, the workspace)
+which can't be reached from the roots, recursion lists recursive functions,
+unsafe lists the safe exported functions which can reach unsafe code, panics
+lists the functions which can panic, no-alloc lists the functions marked
+`#[callgraph::no_alloc]` which can allocate, and path lists paths of calls from
+FROM to TO. diff compares two saved graphs
+(e.g., written with --merge --format json), without running Cargo.
+
+Options:
+    -o, --output PATH      write the graph to PATH ('-' for stdout), only
+                           with --merge unless PATH is '-'
+                           [default: <crate_name>-<kind>.<format>, where kind
+                           is lib, bin, proc-macro, or test]
+    --output-dir DIR       directory for the default output path
+    -f, --format FORMAT    output format: dot, text, or json [default: dot]
+    --merge                merge the graphs of all crates into one, called
+                           merged.<format>
+    --skip-unconnected     drop functions which have no calls to or from them
+    --collapse-external    show each other crate as a single node
+    --include PATTERN      only show functions matching PATTERN, can be
+                           repeated
+    --exclude PATTERN      don't show functions matching PATTERN, can be
+                           repeated
+    --keep-boundary        with --include or --exclude, also show functions
+                           which call or are called by the functions shown
+    --aggregate LEVEL      show a node per module (LEVEL is `module`), or per
+                           impl and module (`impl`), rather than per function
+    --cluster-modules      draw the functions of each module in a cluster
+    --dump                 also dump the collected information to stdout
+    --mono                 graph monomorphised instances reachable from main
+                           and exported functions
+    --cluster-sccs         draw each set of mutually recursive functions
+                           (and each directly recursive function) in a cluster
+    --depth N              with callers or callees, follow at most N calls
+    --definite-only        with callers, callees, path, unsafe, panics, or
+                           no-alloc, ignore potential calls
+    --shortest K           with path, list the K shortest paths [default: 1]
+    --all N                with path, list all paths of at most N calls
+    --roots LIST           with dead-code, the roots to start from, a comma
+                           separated list of main, pub, test, exports, and
+                           trait-impls [default: all of them]
+    --list FILE            with no-alloc, also check the functions matching
+                           the patterns in FILE, one per line
+    -h, --help             print this message
+";
+
+// A value of WRAPPER_RUN_ENV which differs from any earlier run's.
+fn run_id() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}-{}", now.as_nanos(), process::id())
+}
+
+// The JSON graphs of each crate found in dir.
+fn saved_graphs(dir: &Path) -> Vec<PathBuf> {
+    let mut graphs: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().map_or(false, |ext| ext == "json"))
+                .collect()
+        })
+        .unwrap_or_default();
+    graphs.sort();
+    graphs
+}
+
+fn main() {
+    // Cargo runs us as `cargo-callgraph callgraph ARGS...`.
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map_or(false, |a| a == "callgraph") {
+        args.remove(0);
+    }
+
+    let sep = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    let mut opts = args[..sep].to_vec();
+    let cargo_args = args.get(sep + 1..).unwrap_or(&[]);
+
+    if opts.iter().any(|a| a == "-h" || a == "--help") {
+        print!("{}", USAGE);
+        process::exit(0);
+    }
+    let merge = opts.iter().any(|a| a == "--merge");
+    opts.retain(|a| a != "--merge");
+    // Check the options now, rather than once per crate in the wrapper.
+    let config = match Config::parse(&opts) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+    if config.diff.is_some() {
+        process::exit(callgraph::run_diff(config));
+    }
+    // Each crate would write its graph to the same file.
+    if !merge && config.output.as_ref().map_or(false, |path| path != Path::new("-")) {
+        eprintln!("error: -o needs --merge, use --output-dir to choose where each crate's \
+                   graph goes\n\n{}", USAGE);
+        process::exit(1);
+    }
+
+    // When merging, each crate's graph is saved as JSON in graph_dir, then
+    // the graphs are merged and output as the options ask. Each run has its own
+    // graph_dir, so only the crates checked this time are merged.
+    let run = run_id();
+    let target_dir = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("target"));
+    let graph_dir = env::current_dir().expect("current directory")
+        .join(target_dir)
+        .join("callgraph")
+        .join(&run);
+    if merge {
+        fs::create_dir_all(&graph_dir).expect("could not create graph directory");
+        opts = vec!["--format".to_owned(), "json".to_owned(),
+                    "--output-dir".to_owned(), graph_dir.display().to_string()];
+        if config.mono {
+            opts.push("--mono".to_owned());
+        }
+    }
+
+    let driver = env::current_exe()
+        .expect("current executable")
+        .with_file_name(format!("callgraph{}", env::consts::EXE_SUFFIX));
+    let wrapper_args = opts.join(&callgraph::WRAPPER_ARGS_SEP.to_string());
+
+    // The driver can only read crates compiled by its own rustc, so Cargo must
+    // compile the dependencies with that too, not the user's toolchain. With
+    // rustup, we run our toolchain's cargo (which also lets the driver find its
+    // libraries), otherwise the cargo which ran us.
+    let mut cargo = if env::var_os("RUSTUP_HOME").is_some() {
+        let mut cargo = Command::new("cargo");
+        cargo.env("RUSTUP_TOOLCHAIN", callgraph::toolchain()).env_remove("RUSTC");
+        cargo
+    } else {
+        Command::new(env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo")))
+    };
+    let status = cargo
+        .arg("check")
+        .args(cargo_args)
+        .env("RUSTC_WORKSPACE_WRAPPER", driver)
+        .env(callgraph::WRAPPER_ARGS_ENV, wrapper_args)
+        .env(callgraph::WRAPPER_RUN_ENV, &run)
+        .status()
+        .expect("could not run cargo");
+    if !merge {
+        process::exit(status.code().unwrap_or(1));
+    }
+
+    let code = if status.success() {
+        callgraph::run_saved(Config { load: saved_graphs(&graph_dir), ..config })
+    } else {
+        status.code().unwrap_or(1)
+    };
+    let _ = fs::remove_dir_all(&graph_dir);
+    process::exit(code);
+}
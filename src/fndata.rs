@@ -1,49 +1,1718 @@
-use std::collections::{HashSet,HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::graphviz::{Colors, DotOptions};
+use crate::pattern::Pattern;
+
+use std::collections::{HashSet,HashMap,VecDeque};
+use std::fmt;
 use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::Path;
+
+
+// Identifies a function. This is a DefId flattened to a pair of numbers, like
+// the rls_data::Id we used to get from save-analysis. The krate number is only
+// meaningful within a single compilation.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub struct Id {
+    pub krate: u32,
+    pub index: u32,
+}
+
+// A crate-independent identity for a function: the same function has the same
+// FnPath in every compilation, whichever crate we find it from.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub struct FnPath {
+    pub krate: String,
+    // Distinguishes crates with the same name (e.g., two versions of a crate).
+    // This is the crate's StableCrateId.
+    pub disambiguator: u64,
+    // The path to the function within its crate, e.g., `::{impl#0}::m1`. Empty
+    // for a node which stands for a whole crate.
+    pub def_path: String,
+}
+
+// A position in the source code. Lines and columns start at 1.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub struct SourceLoc {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+// What sort of function a node is.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FnKind {
+    FreeFn,
+    InherentMethod,
+    // A trait method without a default implementation. These are only nodes
+    // if they are from another crate, see FnVisitor::post_process.
+    TraitMethod,
+    TraitDefaultMethod,
+    TraitImplMethod,
+    // A closure, or an async block (or other coroutine), see CallKind::Contains.
+    Closure,
+    AsyncBlock,
+    // The initializer of a const or static (including associated consts).
+    Const,
+    Static,
+    // A whole crate, see FnData::collapse_external.
+    Crate,
+    // A module, or an impl (or trait), see FnData::aggregate.
+    Module,
+    Impl,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FnVisibility {
+    // `pub`.
+    Public,
+    // Visible in the whole crate, e.g., `pub(crate)`, or `pub(super)` in a
+    // module at the crate root.
+    Crate,
+    // Visible in some module other than its own or the crate root, e.g.,
+    // `pub(super)`.
+    Restricted,
+    // Only visible in its own module, even if that is the crate root.
+    Private,
+}
+
+// A node in the callgraph, a function and what we know about it.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct FnNode {
+    // The name we show for the function.
+    pub name: String,
+    pub path: FnPath,
+    // The function is from another crate, we know it is called, but not what
+    // it calls.
+    pub external: bool,
+    pub kind: FnKind,
+    // The def path of the module the function is in, skipping any functions
+    // (etc.) in between, e.g., `::foo` for a const in `::foo::bar`.
+    pub module: String,
+    // Where the function is defined. Crate nodes don't have a span.
+    pub span: Option<SourceLoc>,
+    pub visibility: FnVisibility,
+    #[serde(rename = "unsafe")]
+    pub is_unsafe: bool,
+    #[serde(rename = "async")]
+    pub is_async: bool,
+    #[serde(rename = "const")]
+    pub is_const: bool,
+    // The ABI, if the function is `extern`, e.g., "C".
+    pub abi: Option<String>,
+    // The names of the function's own generic parameters (i.e., not including
+    // those of its impl or trait).
+    pub generics: Vec<String>,
+    // The crate's `main` function.
+    pub entry: bool,
+    // A `#[test]` function.
+    pub test: bool,
+    // Reachable from outside the crate, i.e., the function is `pub` and so are
+    // the modules it is in (or it is re-exported).
+    pub exported: bool,
+    // `#[no_mangle]`, or the function otherwise has a fixed symbol name.
+    pub no_mangle: bool,
+    // A function from std (or core) which panics, see visitor::is_panic.
+    pub panic: bool,
+    // A function from std (or alloc) which allocates, see visitor::is_alloc.
+    pub alloc: bool,
+    // Marked `#[callgraph::no_alloc]`, so must not allocate.
+    pub no_alloc: bool,
+}
+
+impl FnNode {
+    // A node standing for the whole crate of path.
+    pub fn krate(path: &FnPath) -> FnNode {
+        let path = FnPath { def_path: String::new(), ..path.clone() };
+        FnNode::group(path.krate.clone(), path, FnKind::Crate, true)
+    }
+
+    // A node standing for a group of functions, e.g., a module.
+    pub fn group(name: String, path: FnPath, kind: FnKind, external: bool) -> FnNode {
+        FnNode {
+            name,
+            module: parent_path(&path.def_path).to_owned(),
+            path,
+            external,
+            kind,
+            span: None,
+            visibility: FnVisibility::Public,
+            is_unsafe: false,
+            is_async: false,
+            is_const: false,
+            abi: None,
+            generics: vec![],
+            entry: false,
+            test: false,
+            exported: true,
+            no_mangle: false,
+            panic: false,
+            alloc: false,
+            no_alloc: false,
+        }
+    }
+
+    // The def path of the module the function is in, e.g., `::foo::bar`, or
+    // the empty string for the crate root. For nodes which stand for a group
+    // of functions, this is the group's path.
+    pub fn module_path(&self) -> &str {
+        match self.kind {
+            FnKind::Crate | FnKind::Module | FnKind::Impl => &self.path.def_path,
+            _ => &self.module,
+        }
+    }
+
+    // The def path of the impl or trait which the method is in, e.g.,
+    // `::foo::{impl#0}`, or of the module for other functions.
+    pub fn impl_path(&self) -> &str {
+        match self.kind {
+            FnKind::InherentMethod
+            | FnKind::TraitMethod
+            | FnKind::TraitDefaultMethod
+            | FnKind::TraitImplMethod => parent_path(self.def_path()),
+            _ => self.module_path(),
+        }
+    }
+
+    // The def path without the generic arguments added in mono mode.
+    fn def_path(&self) -> &str {
+        self.path.def_path.split("::<").next().unwrap()
+    }
+}
+
+// The path of the parent of the item with def path path, e.g., `::foo` for
+// `::foo::bar`.
+fn parent_path(path: &str) -> &str {
+    &path[..path.rfind("::").unwrap_or(0)]
+}
+
+// The name path would have without its last segment, e.g., `<Foo as Bar>` for
+// `<Foo as Bar>::baz`. Separators inside generic arguments don't count.
+fn parent_name(name: &str) -> &str {
+    let mut depth = 0;
+    let mut last = None;
+    for (i, c) in name.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            ':' if depth == 0 && name[i..].starts_with("::") => last = Some(i),
+            _ => {}
+        }
+    }
+    match last {
+        Some(i) => &name[..i],
+        None => name,
+    }
+}
 
-use rls_data::Id;
+// What the nodes of an aggregated graph stand for, see FnData::aggregate.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Aggregation {
+    Module,
+    // Impls (and traits) for methods, and modules for other functions.
+    Impl,
+}
+
+// Whether a call certainly happens (e.g., static dispatch) or only might happen
+// (e.g., all possible receiving methods of dynamic dispatch, or all functions a
+// fn pointer might point to). Contains edges are not calls, but go from a
+// function to the closures, async blocks, consts, and statics defined in it. We
+// follow them like calls, since those are run (or evaluated) on behalf of the
+// function. AddressTaken edges are not calls either, but go from a function to
+// those it uses without calling them (e.g., passes to `map`, or casts to a fn
+// pointer). We follow them like Potential calls, since the function might be
+// called later.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallKind {
+    Definite,
+    Potential,
+    Contains,
+    AddressTaken,
+}
+
+impl CallKind {
+    pub const ALL: [CallKind; 4] = [CallKind::Definite,
+                                    CallKind::Potential,
+                                    CallKind::Contains,
+                                    CallKind::AddressTaken];
+
+    // Whether the edge is only followed when following Potential calls.
+    pub fn is_potential(self) -> bool {
+        matches!(self, CallKind::Potential | CallKind::AddressTaken)
+    }
+}
+
+// Which way to follow calls through the graph.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    // From callers to the functions they call.
+    Callees,
+    // From callees to the functions which call them.
+    Callers,
+}
+
+// A reachability query, see FnData::query.
+#[derive(Clone, Debug)]
+pub struct Query {
+    pub direction: Direction,
+    pub pattern: Pattern,
+    // The most calls to follow from a matching function, None for no limit.
+    pub max_depth: Option<usize>,
+    // Whether to follow Potential calls.
+    pub potential: bool,
+}
+
+// Which functions count as roots when looking for dead code, see
+// FnData::unreachable.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Roots {
+    // The crate's `main` function.
+    pub main: bool,
+    // Functions which can be called from other crates, e.g., the `pub`
+    // functions of a library.
+    pub public: bool,
+    // `#[test]` functions.
+    pub tests: bool,
+    // `#[no_mangle]` and `extern` (e.g., `extern "C"`) functions.
+    pub exports: bool,
+    // Methods of trait impls and default methods, which may be called through
+    // generics or trait objects from other crates.
+    pub trait_impls: bool,
+}
+
+impl Roots {
+    // The names used by parse.
+    pub const NAMES: &'static [&'static str] = &["main", "pub", "test", "exports", "trait-impls"];
+
+    pub fn all() -> Roots {
+        Roots { main: true, public: true, tests: true, exports: true, trait_impls: true }
+    }
 
+    // Parse a comma separated list of the kinds of roots, e.g., `main,test`.
+    pub fn parse(list: &str) -> Result<Roots, String> {
+        let mut roots = Roots {
+            main: false,
+            public: false,
+            tests: false,
+            exports: false,
+            trait_impls: false,
+        };
+        for name in list.split(',').filter(|n| !n.is_empty()) {
+            match name {
+                "main" => roots.main = true,
+                "pub" => roots.public = true,
+                "test" => roots.tests = true,
+                "exports" => roots.exports = true,
+                "trait-impls" => roots.trait_impls = true,
+                _ => return Err(format!("unknown root `{}`, expected one of {}",
+                                        name, Roots::NAMES.join(", "))),
+            }
+        }
+        Ok(roots)
+    }
+
+    pub fn contains(&self, node: &FnNode) -> bool {
+        (self.main && node.entry)
+            || (self.public && node.exported)
+            || (self.tests && node.test)
+            || (self.exports && (node.no_mangle || node.abi.is_some()))
+            || (self.trait_impls && matches!(node.kind, FnKind::TraitImplMethod |
+                                                        FnKind::TraitDefaultMethod))
+    }
+}
+
+// Which paths between two functions to find, see FnData::shortest_paths and
+// FnData::simple_paths.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PathSearch {
+    // The k shortest paths.
+    Shortest(usize),
+    // All paths with at most this many calls.
+    All(usize),
+}
+
+// An edge in the callgraph.
+pub type Edge = (Id, Id, CallKind);
+
+// How a call is written: as a call of a path (`foo(x)`, `Foo::bar(x)`, or a
+// closure `f(x)`), with method call syntax (`x.bar()`), or not at all, i.e.,
+// the compiler inserted it (overloaded operators, Deref, Drop, for loops, `?`,
+// bounds and overflow checks, etc.). A call through a fn pointer has the
+// syntax of the call of the pointer, usually Direct (`f(x)`), whichever
+// function it might go to.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CallSyntax {
+    Direct,
+    Method,
+    Implicit,
+}
+
+// The calls from one function to another.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct CallSites {
+    // Where the calls are, without duplicates.
+    pub sites: Vec<SourceLoc>,
+    // How many calls there are. This can be more than the number of sites,
+    // e.g., a value may be dropped in the same place on several paths.
+    pub count: usize,
+    // The sites which are in unsafe blocks, also in sites.
+    pub unsafe_sites: Vec<SourceLoc>,
+    // The sites of method calls and implicit calls (see CallSyntax), also in
+    // sites. Other sites of calls are direct.
+    pub method_sites: Vec<SourceLoc>,
+    pub implicit_sites: Vec<SourceLoc>,
+}
+
+impl CallSites {
+    pub fn add(&mut self, site: SourceLoc, in_unsafe: bool) {
+        self.count += 1;
+        if in_unsafe && !self.unsafe_sites.contains(&site) {
+            self.unsafe_sites.push(site.clone());
+        }
+        if !self.sites.contains(&site) {
+            self.sites.push(site);
+        }
+    }
+
+    // Add the site of a call, written as syntax.
+    pub fn add_call(&mut self, site: SourceLoc, syntax: CallSyntax, in_unsafe: bool) {
+        let syntax_sites = match syntax {
+            CallSyntax::Direct => None,
+            CallSyntax::Method => Some(&mut self.method_sites),
+            CallSyntax::Implicit => Some(&mut self.implicit_sites),
+        };
+        if let Some(syntax_sites) = syntax_sites {
+            if !syntax_sites.contains(&site) {
+                syntax_sites.push(site.clone());
+            }
+        }
+        self.add(site, in_unsafe);
+    }
+
+    fn dump<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "    {} call(s) at:", self.count)?;
+        for site in self.sites.iter() {
+            let syntax = if self.method_sites.contains(site) {
+                " (method)"
+            } else if self.implicit_sites.contains(site) {
+                " (implicit)"
+            } else {
+                ""
+            };
+            let in_unsafe = if self.unsafe_sites.contains(site) { " (unsafe)" } else { "" };
+            writeln!(out, "        {}{}{}", site, syntax, in_unsafe)?;
+        }
+        Ok(())
+    }
+
+    pub fn extend(&mut self, other: &CallSites) {
+        self.count += other.count;
+        for (sites, other_sites) in [(&mut self.sites, &other.sites),
+                                     (&mut self.unsafe_sites, &other.unsafe_sites),
+                                     (&mut self.method_sites, &other.method_sites),
+                                     (&mut self.implicit_sites, &other.implicit_sites)] {
+            for site in other_sites.iter() {
+                if !sites.contains(site) {
+                    sites.push(site.clone());
+                }
+            }
+        }
+    }
+}
+
+// Calls between functions, (caller, callee) -> call sites.
+pub type Calls = HashMap<(Id, Id), CallSites>;
+
+// Map the callers and callees of calls to new ids. If several calls map to the
+// same pair, their call sites are combined.
+fn map_calls<F: Fn(Id) -> Id>(calls: &Calls, map: F) -> Calls {
+    let mut result = Calls::new();
+    for (&(from, to), sites) in calls.iter() {
+        result.entry((map(from), map(to))).or_default().extend(sites);
+    }
+    result
+}
 
 // Processed data about our crate. See comments on visitor::FnVisitor for more
 // detail.
 pub struct FnData {
-    pub static_calls: HashSet<(Id, Id)>,
+    pub static_calls: Calls,
     // (caller def, callee def) c.f., FnVisitor::dynamic_calls.
-    pub dynamic_calls: HashSet<(Id, Id)>,    
-    pub functions: HashMap<Id, String>,
+    pub dynamic_calls: Calls,
+    // (parent, child), see CallKind::Contains.
+    pub contains: Calls,
+    // (referrer, function), see CallKind::AddressTaken.
+    pub address_taken: Calls,
+    // Functions defined in our crate, and functions from other crates which
+    // are called from it.
+    pub functions: HashMap<Id, FnNode>,
 
     pub crate_name: String
 }
 
 impl FnData {
-    // Make a graphviz dot file.
+    pub fn new(crate_name: &str) -> FnData {
+        FnData {
+            static_calls: Calls::new(),
+            dynamic_calls: Calls::new(),
+            contains: Calls::new(),
+            address_taken: Calls::new(),
+            functions: HashMap::new(),
+            crate_name: crate_name.to_string(),
+        }
+    }
+
+    // Read a graph saved in JSON format by save.
+    pub fn load(path: &Path) -> io::Result<FnData> {
+        crate::json::read(BufReader::new(File::open(path)?))
+    }
+
+    // Write the graph in JSON format.
+    pub fn save<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        crate::json::write(self, out)
+    }
+
+    // Combine the graphs of several crates (e.g., the members of a workspace)
+    // into one called crate_name. Functions are matched by their FnPath, so a
+    // call from one crate to an external function which is defined by another
+    // links to that function's node.
+    //
+    // Ids in the result are renumbered, the Ids of each crate only make sense
+    // within the compilation of that crate.
+    pub fn merge(graphs: Vec<FnData>, crate_name: &str) -> FnData {
+        let mut merged = FnData::new(crate_name);
+        let mut ids: HashMap<FnPath, Id> = HashMap::new();
+        let mut crates: HashMap<(String, u64), u32> = HashMap::new();
+
+        for data in graphs {
+            // Number functions in the order of their old Ids, so the result
+            // doesn't depend on HashMap order.
+            let mut old_ids: Vec<&Id> = data.functions.keys().collect();
+            old_ids.sort();
+            let mut map = HashMap::with_capacity(data.functions.len());
+            for id in old_ids {
+                let node = &data.functions[id];
+                let path = &node.path;
+                let crate_count = crates.len() as u32;
+                let krate = *crates.entry((path.krate.clone(), path.disambiguator))
+                    .or_insert(crate_count);
+                let fn_count = ids.len() as u32;
+                let new_id = *ids.entry(path.clone())
+                    .or_insert(Id { krate, index: fn_count });
+                map.insert(*id, new_id);
+
+                // Don't replace a definition with a call from another crate.
+                if !node.external || !merged.functions.contains_key(&new_id) {
+                    merged.functions.insert(new_id, node.clone());
+                }
+            }
+
+            for kind in CallKind::ALL {
+                for ((from, to), sites) in map_calls(data.calls(kind), |id| map[&id]) {
+                    merged.calls_mut(kind).entry((from, to)).or_default().extend(&sites);
+                }
+            }
+        }
+
+        merged
+    }
+
+    // Compare two graphs of the same code (e.g., before and after a change).
+    // Functions are matched by their FnPath, so the graphs may come from
+    // different compilations.
+    pub fn diff(old: FnData, new: FnData) -> GraphDiff {
+        // Paths are our common currency, since Ids differ between graphs.
+        fn paths(data: &FnData) -> (HashSet<FnPath>, HashSet<(FnPath, FnPath, CallKind)>) {
+            let path = |id: Id| data.functions[&id].path.clone();
+            let fns = data.functions.values().map(|node| node.path.clone()).collect();
+            let calls = data.edges()
+                .map(|(from, to, kind)| (path(from), path(to), kind))
+                .collect();
+            (fns, calls)
+        }
+        let (old_fns, old_calls) = paths(&old);
+        let (new_fns, new_calls) = paths(&new);
+
+        // Calls in both graphs are shown as they are in new.
+        let mut old = old;
+        let functions = &old.functions;
+        let in_new = |&(from, to): &(Id, Id), kind| {
+            new_calls.contains(&(functions[&from].path.clone(), functions[&to].path.clone(), kind))
+        };
+        old.static_calls.retain(|call, _| !in_new(call, CallKind::Definite));
+        old.dynamic_calls.retain(|call, _| !in_new(call, CallKind::Potential));
+        old.contains.retain(|call, _| !in_new(call, CallKind::Contains));
+        old.address_taken.retain(|call, _| !in_new(call, CallKind::AddressTaken));
+
+        let crate_name = format!("{}_diff", new.crate_name);
+        let data = FnData::merge(vec![old, new], &crate_name);
+        let path = |id: Id| data.functions[&id].path.clone();
+
+        let mut added_fns = vec![];
+        let mut removed_fns = vec![];
+        for (&id, node) in data.functions.iter() {
+            match (old_fns.contains(&node.path), new_fns.contains(&node.path)) {
+                (false, true) => added_fns.push(id),
+                (true, false) => removed_fns.push(id),
+                _ => {}
+            }
+        }
+        let mut added_calls = vec![];
+        let mut removed_calls = vec![];
+        for edge in data.edges() {
+            let (from, to, kind) = edge;
+            let call = (path(from), path(to), kind);
+            match (old_calls.contains(&call), new_calls.contains(&call)) {
+                (false, true) => added_calls.push(edge),
+                (true, false) => removed_calls.push(edge),
+                _ => {}
+            }
+        }
+        added_fns.sort();
+        removed_fns.sort();
+        added_calls.sort_by_key(|&(from, to, _)| (from, to));
+        removed_calls.sort_by_key(|&(from, to, _)| (from, to));
+
+        GraphDiff { data, added_fns, removed_fns, added_calls, removed_calls }
+    }
+
+    // All edges, static calls are Definite and dynamic ones Potential.
+    pub fn edges(&self) -> impl Iterator<Item = Edge> + '_ {
+        CallKind::ALL.iter()
+            .flat_map(move |&kind| self.calls(kind).keys().map(move |&(f, t)| (f, t, kind)))
+    }
+
+    // The edges of kind.
+    pub fn calls(&self, kind: CallKind) -> &Calls {
+        match kind {
+            CallKind::Definite => &self.static_calls,
+            CallKind::Potential => &self.dynamic_calls,
+            CallKind::Contains => &self.contains,
+            CallKind::AddressTaken => &self.address_taken,
+        }
+    }
+
+    pub fn calls_mut(&mut self, kind: CallKind) -> &mut Calls {
+        match kind {
+            CallKind::Definite => &mut self.static_calls,
+            CallKind::Potential => &mut self.dynamic_calls,
+            CallKind::Contains => &mut self.contains,
+            CallKind::AddressTaken => &mut self.address_taken,
+        }
+    }
+
+    // Where the calls represented by edge happen. For a Contains edge, that
+    // is where the child is defined.
+    pub fn call_sites(&self, &(from, to, kind): &Edge) -> &CallSites {
+        &self.calls(kind)[&(from, to)]
+    }
+
+    // The functions whose name matches pattern.
+    pub fn find(&self, pattern: &Pattern) -> Vec<Id> {
+        let mut ids: Vec<Id> = self.functions.iter()
+            .filter(|(_, node)| pattern.matches_node(node))
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    // The functions reachable from roots (including the roots themselves) by
+    // following calls in direction. Follows at most max_depth calls if it is
+    // given, and Potential calls only if potential is true.
+    pub fn reachable(&self,
+                     roots: &[Id],
+                     direction: Direction,
+                     max_depth: Option<usize>,
+                     potential: bool)
+                     -> HashSet<Id> {
+        let next = self.adjacency(direction, potential);
+
+        // Breadth first, so we find everything within max_depth.
+        let mut reached: HashSet<Id> = roots.iter().cloned().collect();
+        let mut frontier = roots.to_vec();
+        let mut depth = 0;
+        while !frontier.is_empty() && max_depth.map_or(true, |max| depth < max) {
+            let mut new_frontier = vec![];
+            for id in frontier {
+                for &n in next.get(&id).into_iter().flatten() {
+                    if reached.insert(n) {
+                        new_frontier.push(n);
+                    }
+                }
+            }
+            frontier = new_frontier;
+            depth += 1;
+        }
+        reached
+    }
+
+    // For each function, the functions next to it in direction. Potential
+    // calls are only included if potential is true. The lists are sorted, so
+    // walks of the graph are deterministic.
+    fn adjacency(&self, direction: Direction, potential: bool) -> HashMap<Id, Vec<Id>> {
+        self.adjacency_of(direction, |kind| potential || !kind.is_potential())
+    }
+
+    // Like adjacency, but following the edges of the kinds for which follow
+    // returns true.
+    fn adjacency_of<F: Fn(CallKind) -> bool>(&self, direction: Direction, follow: F)
+                                             -> HashMap<Id, Vec<Id>> {
+        let mut next: HashMap<Id, Vec<Id>> = HashMap::new();
+        for (from, to, kind) in self.edges() {
+            if !follow(kind) {
+                continue;
+            }
+            match direction {
+                Direction::Callees => next.entry(from).or_default().push(to),
+                Direction::Callers => next.entry(to).or_default().push(from),
+            }
+        }
+        for ids in next.values_mut() {
+            ids.sort();
+            ids.dedup();
+        }
+        next
+    }
+
+    // The strongly connected components of the graph (following all calls),
+    // using Tarjan's algorithm. Each component is sorted, and components are
+    // in reverse topological order (a component comes before any which call
+    // it). Taking a function's address doesn't call it (calls through the
+    // pointer are Potential calls of their own), so we don't follow those
+    // edges.
+    pub fn sccs(&self) -> Vec<Vec<Id>> {
+        let next = self.adjacency_of(Direction::Callees, |kind| kind != CallKind::AddressTaken);
+        let mut ids: Vec<Id> = self.functions.keys().cloned().collect();
+        ids.sort();
+
+        let mut index: HashMap<Id, usize> = HashMap::new();
+        let mut lowlink: HashMap<Id, usize> = HashMap::new();
+        let mut stack: Vec<Id> = vec![];
+        let mut on_stack: HashSet<Id> = HashSet::new();
+        let mut sccs = vec![];
+
+        // We keep our own stack of (function, how many of its callees we
+        // have visited), rather than recursing, since call chains can be long.
+        for root in ids {
+            if index.contains_key(&root) {
+                continue;
+            }
+            let mut work = vec![(root, 0)];
+            while let Some(&(v, i)) = work.last() {
+                if i == 0 {
+                    let n = index.len();
+                    index.insert(v, n);
+                    lowlink.insert(v, n);
+                    stack.push(v);
+                    on_stack.insert(v);
+                }
+
+                let callees = next.get(&v).map_or(&[][..], |c| &c[..]);
+                if i < callees.len() {
+                    work.last_mut().unwrap().1 += 1;
+                    let w = callees[i];
+                    if !index.contains_key(&w) {
+                        work.push((w, 0));
+                    } else if on_stack.contains(&w) {
+                        let low = lowlink[&v].min(index[&w]);
+                        lowlink.insert(v, low);
+                    }
+                    continue;
+                }
+
+                // We've visited all of v's callees.
+                work.pop();
+                if let Some(&(u, _)) = work.last() {
+                    let low = lowlink[&u].min(lowlink[&v]);
+                    lowlink.insert(u, low);
+                }
+                if lowlink[&v] == index[&v] {
+                    let mut scc = vec![];
+                    loop {
+                        let w = stack.pop().expect("Tarjan stack underflow");
+                        on_stack.remove(&w);
+                        scc.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    scc.sort();
+                    sccs.push(scc);
+                }
+            }
+        }
+        sccs
+    }
+
+    // The recursive SCCs: those with more than one function (mutual
+    // recursion), or a single function which calls itself (direct recursion).
+    pub fn recursion(&self) -> Vec<Vec<Id>> {
+        self.sccs().into_iter()
+            .filter(|scc| {
+                let id = scc[0];
+                scc.len() > 1
+                    || self.static_calls.contains_key(&(id, id))
+                    || self.dynamic_calls.contains_key(&(id, id))
+            })
+            .collect()
+    }
+
+    // Up to k of the shortest paths (i.e., with the fewest calls) from any of
+    // from to any of to, shortest first. No function appears twice in a path,
+    // except that a path may end where it started (e.g., at a recursive
+    // function). Potential calls are only followed if potential is true. This
+    // is Yen's algorithm: each path after the first is the shortest which
+    // diverges from the paths found so far. Paths have at least one call, even
+    // if a function is in both from and to.
+    pub fn shortest_paths(&self, from: &[Id], to: &[Id], k: usize, potential: bool)
+                          -> Vec<Vec<Edge>> {
+        let next = self.adjacency(Direction::Callees, potential);
+        let targets: HashSet<Id> = to.iter().cloned().collect();
+
+        let mut found: Vec<Vec<Id>> = vec![];
+        let mut candidates: Vec<Vec<Id>> = vec![];
+        if let Some(path) = bfs_path(&next, from, &targets, &HashSet::new(), &HashSet::new()) {
+            found.push(path);
+        }
+        while !found.is_empty() && found.len() < k {
+            let last = found.last().unwrap().clone();
+            // Find the shortest path which shares its first i functions with
+            // last, but not its (i+1)th. If i is 0, that is the first function,
+            // otherwise it is the callee of the ith (the spur).
+            for i in 0..last.len() {
+                let root = &last[..i];
+                let banned_next: HashSet<Id> = found.iter()
+                    .filter(|p| p.len() > i && p[..i] == *root)
+                    .map(|p| p[i])
+                    .collect();
+                let spur_path = match root.split_last() {
+                    None => {
+                        let starts: Vec<Id> = from.iter()
+                            .filter(|id| !banned_next.contains(id))
+                            .cloned()
+                            .collect();
+                        bfs_path(&next, &starts, &targets, &HashSet::new(), &HashSet::new())
+                    }
+                    Some((&spur, before)) => {
+                        let banned: HashSet<Id> = before.iter().cloned().collect();
+                        bfs_path(&next, &[spur], &targets, &banned, &banned_next)
+                    }
+                };
+                if let Some(spur_path) = spur_path {
+                    let mut path = root[..i.saturating_sub(1)].to_vec();
+                    path.extend(spur_path);
+                    if !found.contains(&path) && !candidates.contains(&path) {
+                        candidates.push(path);
+                    }
+                }
+            }
+
+            match candidates.iter().enumerate().min_by_key(|(_, p)| (p.len(), *p)) {
+                Some((best, _)) => found.push(candidates.swap_remove(best)),
+                None => break,
+            }
+        }
+
+        found.iter().map(|path| self.path_edges(path)).collect()
+    }
+
+    // All paths from any of from to any of to with at most max_len calls,
+    // shortest first. No function appears twice in a path (except as its first
+    // and last), and a path ends at the first function of to it reaches after
+    // at least one call. Potential calls are only followed if potential is
+    // true.
+    pub fn simple_paths(&self, from: &[Id], to: &[Id], max_len: usize, potential: bool)
+                        -> Vec<Vec<Edge>> {
+        let next = self.adjacency(Direction::Callees, potential);
+        let targets: HashSet<Id> = to.iter().cloned().collect();
+
+        let mut found = vec![];
+        for &start in from {
+            walk_paths(&next, &targets, &mut vec![start], max_len, &mut found);
+        }
+        found.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+        found.iter().map(|path| self.path_edges(path)).collect()
+    }
+
+    // The calls along path. Where a function both definitely and potentially
+    // calls the next, we use the Definite call.
+    fn path_edges(&self, path: &[Id]) -> Vec<Edge> {
+        path.windows(2).map(|w| {
+            let (from, to) = (w[0], w[1]);
+            let kind = [CallKind::Definite,
+                        CallKind::Contains,
+                        CallKind::Potential,
+                        CallKind::AddressTaken].iter()
+                .copied()
+                .find(|&kind| self.calls(kind).contains_key(&(from, to)))
+                .expect("no edge between functions of a path");
+            (from, to, kind)
+        }).collect()
+    }
+
+    // The transitive callees of roots, see reachable.
+    pub fn callees(&self, roots: &[Id], max_depth: Option<usize>, potential: bool) -> HashSet<Id> {
+        self.reachable(roots, Direction::Callees, max_depth, potential)
+    }
+
+    // The transitive callers of roots, see reachable.
+    pub fn callers(&self, roots: &[Id], max_depth: Option<usize>, potential: bool) -> HashSet<Id> {
+        self.reachable(roots, Direction::Callers, max_depth, potential)
+    }
+
+    // The functions of our crate(s) which can't be reached from roots, following
+    // Potential calls as well as Definite ones. Sorted by where they are defined.
+    //
+    // We don't see where consts and statics are used, so those which aren't
+    // contained in a function are always roots.
+    pub fn unreachable(&self, roots: &Roots) -> Vec<Id> {
+        let contained: HashSet<Id> = self.contains.keys().map(|&(_, to)| to).collect();
+        let root_ids: Vec<Id> = self.functions.iter()
+            .filter(|(id, node)| {
+                let item = matches!(node.kind, FnKind::Const | FnKind::Static)
+                    && !contained.contains(id);
+                !node.external && (roots.contains(node) || item)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        let reached = self.callees(&root_ids, None, true);
+
+        let mut unreached: Vec<Id> = self.functions.iter()
+            .filter(|(id, node)| !node.external && !reached.contains(id))
+            .map(|(id, _)| *id)
+            .collect();
+        unreached.sort_by_key(|id| (&self.functions[id].span, *id));
+        unreached
+    }
+
+    // The calls which are unsafe code: those made in unsafe blocks, and those to
+    // unsafe functions which are written in the source (e.g., in an unsafe fn).
+    // The compiler inserts calls to unsafe functions in safe code (e.g., to
+    // `exchange_malloc` for `vec![..]`), so Implicit calls to them don't count.
+    // Sorted by caller and callee.
+    pub fn unsafe_calls(&self) -> Vec<Edge> {
+        let mut edges: Vec<Edge> = self.edges()
+            .filter(|edge| !self.unsafe_sites(edge).is_empty())
+            .collect();
+        edges.sort_by_key(|&(from, to, _)| (from, to));
+        edges
+    }
+
+    // The sites of the calls of edge which are unsafe code, see unsafe_calls.
+    pub fn unsafe_sites(&self, edge: &Edge) -> Vec<&SourceLoc> {
+        let sites = self.call_sites(edge);
+        let (_, to, kind) = *edge;
+        let to_unsafe = matches!(kind, CallKind::Definite | CallKind::Potential)
+            && self.functions[&to].is_unsafe;
+        sites.sites.iter()
+            .filter(|site| {
+                sites.unsafe_sites.contains(site)
+                    || (to_unsafe && !sites.implicit_sites.contains(site))
+            })
+            .collect()
+    }
+
+    // The safe functions of our crate(s) which are exported (see Roots) and can
+    // reach unsafe code (see unsafe_calls). Each comes with the shortest path to
+    // a function which makes unsafe calls; the path is empty if the function
+    // makes such calls itself. Sorted by where the functions are defined.
+    pub fn unsafe_reach(&self, potential: bool) -> Vec<(Id, Vec<Edge>)> {
+        let targets: HashSet<Id> = self.unsafe_calls().iter()
+            .map(|&(from, _, _)| from)
+            .collect();
+        self.reach(|node| node.exported && !node.is_unsafe, &targets, potential)
+    }
+
+    // The functions of our crate(s) which can panic, i.e., reach a function
+    // where std panics (see FnNode::panic). Each comes with the shortest path to
+    // one. Sorted by where the functions are defined.
+    pub fn panic_reach(&self, potential: bool) -> Vec<(Id, Vec<Edge>)> {
+        let targets: HashSet<Id> = self.functions.iter()
+            .filter(|(_, node)| node.panic)
+            .map(|(id, _)| *id)
+            .collect();
+        self.reach(|_| true, &targets, potential)
+    }
+
+    // The functions of our crate(s) which must not allocate: those marked
+    // `#[callgraph::no_alloc]`, and those matching any of patterns.
+    pub fn no_alloc_fns(&self, patterns: &[Pattern]) -> Vec<Id> {
+        self.functions.iter()
+            .filter(|(_, node)| !node.external && must_not_alloc(node, patterns))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    // The functions which must not allocate (see no_alloc_fns), but can reach
+    // a function where std allocates (see FnNode::alloc). Each comes with the
+    // shortest path to one. Sorted by where the functions are defined.
+    pub fn alloc_reach(&self, patterns: &[Pattern], potential: bool) -> Vec<(Id, Vec<Edge>)> {
+        let targets: HashSet<Id> = self.functions.iter()
+            .filter(|(_, node)| node.alloc)
+            .map(|(id, _)| *id)
+            .collect();
+        self.reach(|node| must_not_alloc(node, patterns), &targets, potential)
+    }
+
+    // The functions of our crate(s) selected by start which can reach any of
+    // targets, each with the shortest path to one, or an empty path if it is a
+    // target itself. Sorted by where the functions are defined.
+    fn reach<F: Fn(&FnNode) -> bool>(&self, start: F, targets: &HashSet<Id>, potential: bool)
+                                      -> Vec<(Id, Vec<Edge>)> {
+        let next = self.adjacency(Direction::Callees, potential);
+        let mut result: Vec<(Id, Vec<Edge>)> = self.functions.iter()
+            .filter(|(_, node)| !node.external && start(node))
+            .filter_map(|(&id, _)| {
+                if targets.contains(&id) {
+                    return Some((id, vec![]));
+                }
+                bfs_path(&next, &[id], targets, &HashSet::new(), &HashSet::new())
+                    .map(|path| (id, self.path_edges(&path)))
+            })
+            .collect();
+        result.sort_by_key(|(id, _)| (&self.functions[id].span, *id));
+        result
+    }
+
+    // Restrict the graph to the functions in keep and the calls between them.
+    pub fn retain(&mut self, keep: &HashSet<Id>) {
+        self.functions.retain(|id, _| keep.contains(id));
+        for kind in CallKind::ALL {
+            self.calls_mut(kind).retain(|(from, to), _| keep.contains(from) && keep.contains(to));
+        }
+    }
+
+    // Restrict the graph to the functions matching any of include (or all
+    // functions if include is empty), except those matching any of exclude. If
+    // keep_boundary is true, we also keep the functions which call or are
+    // called by those functions, but not the calls between them.
+    pub fn filter(&mut self, include: &[Pattern], exclude: &[Pattern], keep_boundary: bool) {
+        let selected: HashSet<Id> = self.functions.iter()
+            .filter(|(_, node)| {
+                (include.is_empty() || include.iter().any(|p| p.matches_node(node)))
+                    && !exclude.iter().any(|p| p.matches_node(node))
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut keep = selected.clone();
+        if keep_boundary {
+            for (from, to, _) in self.edges() {
+                if selected.contains(&from) || selected.contains(&to) {
+                    keep.insert(from);
+                    keep.insert(to);
+                }
+            }
+        }
+        self.retain(&keep);
+        for kind in CallKind::ALL {
+            self.calls_mut(kind).retain(|(f, t), _| selected.contains(f) || selected.contains(t));
+        }
+    }
+
+    // Restrict the graph to the functions matching query's pattern, the
+    // functions reachable from them, and the calls between those. If query
+    // doesn't follow Potential calls, they are dropped too. Returns false if
+    // no function matches.
+    pub fn query(&mut self, query: &Query) -> bool {
+        let roots = self.find(&query.pattern);
+        let reached = self.reachable(&roots, query.direction, query.max_depth, query.potential);
+        if !query.potential {
+            self.dynamic_calls.clear();
+            self.address_taken.clear();
+        }
+        self.retain(&reached);
+        !roots.is_empty()
+    }
+
+    // Replace functions with a node for their module or impl (see
+    // FnNode::module_path and impl_path). Calls between functions become calls
+    // between those nodes; the count of a call is the number of calls between
+    // functions it stands for. Calls within a module or impl are kept as calls
+    // from the node to itself.
+    pub fn aggregate(&mut self, level: Aggregation) {
+        let mut ids: Vec<Id> = self.functions.keys().cloned().collect();
+        ids.sort();
+
+        let mut groups: HashMap<FnPath, Id> = HashMap::new();
+        let mut nodes: HashMap<Id, FnNode> = HashMap::new();
+        let mut map: HashMap<Id, Id> = HashMap::with_capacity(ids.len());
+        for id in ids {
+            let node = &self.functions[&id];
+            let (def_path, kind) = match level {
+                _ if node.kind == FnKind::Crate => (node.path.def_path.clone(), FnKind::Crate),
+                Aggregation::Impl if node.impl_path() != node.module_path() => {
+                    (node.impl_path().to_owned(), FnKind::Impl)
+                }
+                _ => (node.module_path().to_owned(), FnKind::Module),
+            };
+            let path = FnPath { def_path, ..node.path.clone() };
+
+            let count = groups.len() as u32;
+            let group = *groups.entry(path.clone())
+                .or_insert(Id { krate: id.krate, index: count });
+            map.insert(id, group);
+            nodes.entry(group).or_insert_with(|| {
+                let name = match kind {
+                    FnKind::Crate => node.name.clone(),
+                    FnKind::Impl => parent_name(&node.name).to_owned(),
+                    _ => format!("{}{}", path.krate, path.def_path),
+                };
+                FnNode::group(name, path, kind, node.external)
+            });
+        }
+
+        for kind in CallKind::ALL {
+            *self.calls_mut(kind) = map_calls(self.calls(kind), |id| map[&id]);
+        }
+        // A group containing itself says nothing.
+        self.contains.retain(|(from, to), _| from != to);
+        self.functions = nodes;
+    }
+
+    // Replace the external functions of each crate with a single node for the
+    // crate. Calls to any function of the crate become calls to that node.
+    pub fn collapse_external(&mut self) {
+        // The crate node reuses the crate number of the functions in it, with
+        // an index no DefIndex can have.
+        let crate_node = |id: Id| Id { krate: id.krate, index: u32::MAX };
+        let external: HashSet<Id> = self.functions.iter()
+            .filter(|(_, node)| node.external)
+            .map(|(id, _)| *id)
+            .collect();
+        let map = |id: Id| if external.contains(&id) { crate_node(id) } else { id };
+
+        for kind in CallKind::ALL {
+            *self.calls_mut(kind) = map_calls(self.calls(kind), map);
+        }
+        for id in external {
+            let node = self.functions.remove(&id).expect("missing node");
+            self.functions.insert(crate_node(id), FnNode::krate(&node.path));
+        }
+    }
+
+    // Drop functions which neither call nor are called by any other function.
+    pub fn remove_unconnected(&mut self) {
+        let connected: HashSet<Id> = self.edges().flat_map(|(f, t, _)| vec![f, t]).collect();
+        self.functions.retain(|id, _| connected.contains(id));
+    }
+
+    // Write a graphviz dot file.
     // Must be called after post_process.
-    pub fn dot(&self) {
-        let mut file = File::create(&format!("{}.dot", self.crate_name)).unwrap();
-        rustc_graphviz::render(self, &mut file).unwrap();
+    pub fn dot<W: Write>(&self, options: &DotOptions, out: &mut W) -> io::Result<()> {
+        crate::graphviz::render(self, options, out)
     }
 
-    // Dump collected and processed information to stdout.
-    pub fn dump(&self) {
-        println!("Found fns:");
+    // Dump collected and processed information as text.
+    pub fn dump<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "Found fns:")?;
         for (k, d) in self.functions.iter() {
-            println!("{}:{}: {}", k.krate, k.index, d);
+            writeln!(out, "{}:{}: {}", k.krate, k.index, d.name)?;
+        }
+
+        writeln!(out, "\nFound calls:")?;
+        for ((from, to), sites) in self.static_calls.iter() {
+            let from = &self.functions[from].name;
+            let to = &self.functions[to].name;
+            writeln!(out, "{} -> {}", from, to)?;
+            sites.dump(out)?;
+        }
+
+        writeln!(out, "\nFound potential calls:")?;
+        for ((from, to), sites) in self.dynamic_calls.iter() {
+            let from = &self.functions[from].name;
+            let to = &self.functions[to].name;
+            writeln!(out, "{} -> {}", from, to)?;
+            sites.dump(out)?;
         }
 
-        println!("\nFound calls:");
-        for &(ref from, ref to) in self.static_calls.iter() {
-            println!("{:?} -> {:?}", from, to);
-            let from = &self.functions[from];
-            let to = &self.functions[to];
-            println!("{} -> {}", from, to);
+        writeln!(out, "\nFound contained closures, consts, and statics:")?;
+        for ((from, to), sites) in self.contains.iter() {
+            let from = &self.functions[from].name;
+            let to = &self.functions[to].name;
+            writeln!(out, "{} -> {}", from, to)?;
+            sites.dump(out)?;
         }
 
-        println!("\nFound potential calls:");
-        for &(ref from, ref to) in self.dynamic_calls.iter() {
-            let from = &self.functions[from];
-            let to = &self.functions[to];
-            println!("{} -> {}", from, to);
+        writeln!(out, "\nFound functions whose address is taken:")?;
+        for ((from, to), sites) in self.address_taken.iter() {
+            let from = &self.functions[from].name;
+            let to = &self.functions[to].name;
+            writeln!(out, "{} -> {}", from, to)?;
+            sites.dump(out)?;
         }
+
+        Ok(())
+    }
+
+}
+
+// The difference between two graphs, see FnData::diff.
+pub struct GraphDiff {
+    // Every function and call in either graph.
+    pub data: FnData,
+    pub added_fns: Vec<Id>,
+    pub removed_fns: Vec<Id>,
+    pub added_calls: Vec<Edge>,
+    pub removed_calls: Vec<Edge>,
+}
+
+impl GraphDiff {
+    // Write a graphviz dot file of both graphs, with additions in green and
+    // removals in red.
+    pub fn dot<W: Write>(&self, options: &DotOptions, out: &mut W) -> io::Result<()> {
+        let mut colors = Colors::default();
+        colors.nodes.extend(self.added_fns.iter().map(|&id| (id, "green")));
+        colors.nodes.extend(self.removed_fns.iter().map(|&id| (id, "red")));
+        colors.edges.extend(self.added_calls.iter().map(|&edge| (edge, "green")));
+        colors.edges.extend(self.removed_calls.iter().map(|&edge| (edge, "red")));
+        crate::graphviz::render_colored(&self.data, options, &colors, out)
     }
+}
+
+fn must_not_alloc(node: &FnNode, patterns: &[Pattern]) -> bool {
+    node.no_alloc || patterns.iter().any(|p| p.matches_node(node))
+}
 
+// The shortest path from any of starts to any of targets, with at least one
+// call, as a list of functions. The path may not go through banned, and its
+// first call may not be to any of banned_first. A start may also be the end of
+// a path (e.g., a recursive function), but is never in the middle of one: a
+// path through another start is longer than the path from that start. A
+// target in banned may still end a path, in Yen's algorithm that is the first
+// function of the path, so the path goes back to where it started.
+fn bfs_path(next: &HashMap<Id, Vec<Id>>,
+            starts: &[Id],
+            targets: &HashSet<Id>,
+            banned: &HashSet<Id>,
+            banned_first: &HashSet<Id>)
+            -> Option<Vec<Id>> {
+    let starts: Vec<Id> = starts.iter().filter(|id| !banned.contains(id)).cloned().collect();
+    let start_set: HashSet<Id> = starts.iter().cloned().collect();
+    // Maps each function we reach after at least one call (other than the
+    // starts) to the function we reached it from.
+    let mut parent: HashMap<Id, Id> = HashMap::new();
+    let mut queue: VecDeque<Id> = VecDeque::new();
+    for &start in starts.iter() {
+        if !queue.contains(&start) {
+            queue.push_back(start);
+        }
+    }
+
+    while let Some(id) = queue.pop_front() {
+        let is_start = start_set.contains(&id);
+        for &n in next.get(&id).into_iter().flatten() {
+            if is_start && banned_first.contains(&n) {
+                continue;
+            }
+            if targets.contains(&n) {
+                let mut path = vec![n, id];
+                while let Some(&prev) = parent.get(path.last().unwrap()) {
+                    path.push(prev);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if banned.contains(&n) || start_set.contains(&n) || parent.contains_key(&n) {
+                continue;
+            }
+            parent.insert(n, id);
+            queue.push_back(n);
+        }
+    }
+    None
+}
+
+// Extend path in every way which doesn't repeat a function (except to end where
+// it started), adding those which reach targets within max_len calls to found.
+fn walk_paths(next: &HashMap<Id, Vec<Id>>,
+              targets: &HashSet<Id>,
+              path: &mut Vec<Id>,
+              max_len: usize,
+              found: &mut Vec<Vec<Id>>) {
+    let last = *path.last().unwrap();
+    if targets.contains(&last) && path.len() > 1 {
+        found.push(path.clone());
+        return;
+    }
+    if path.len() > max_len {
+        return;
+    }
+    for &n in next.get(&last).into_iter().flatten() {
+        if !path.contains(&n) || (n == path[0] && targets.contains(&n)) {
+            path.push(n);
+            walk_paths(next, targets, path, max_len, found);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub fn id(index: u32) -> Id {
+        Id { krate: 0, index }
+    }
+
+    // A free function called name in the crate krate.
+    pub fn node(krate: &str, disambiguator: u64, name: &str, external: bool) -> FnNode {
+        let path = FnPath {
+            krate: krate.to_owned(),
+            disambiguator,
+            def_path: format!("::{}", name),
+        };
+        FnNode::group(name.to_owned(), path, FnKind::FreeFn, external)
+    }
+
+    pub fn site(line: u32) -> SourceLoc {
+        SourceLoc { file: "test.rs".to_owned(), line: line as usize, column: 1 }
+    }
+
+    // A graph of free functions named by names, with Definite calls between
+    // them (as indexes into names) and Potential calls.
+    pub fn graph(names: &[&str], calls: &[(u32, u32)], potential: &[(u32, u32)]) -> FnData {
+        let mut data = FnData::new("test");
+        for (i, name) in names.iter().enumerate() {
+            data.functions.insert(id(i as u32), node("test", 0, name, false));
+        }
+        for &(from, to) in calls {
+            data.static_calls.entry((id(from), id(to))).or_default().add(site(from), false);
+        }
+        for &(from, to) in potential {
+            data.dynamic_calls.entry((id(from), id(to))).or_default().add(site(from), false);
+        }
+        data
+    }
+
+    // Paths as the names of the functions along them.
+    fn names(data: &FnData, paths: &[Vec<Edge>]) -> Vec<Vec<String>> {
+        paths.iter().map(|path| {
+            let mut names = vec![data.functions[&path[0].0].name.clone()];
+            names.extend(path.iter().map(|&(_, to, _)| data.functions[&to].name.clone()));
+            names
+        }).collect()
+    }
+
+    fn ids(indexes: &[u32]) -> Vec<Id> {
+        indexes.iter().map(|&i| id(i)).collect()
+    }
+
+    #[test]
+    fn shortest_path() {
+        let data = graph(&["main", "a", "b", "c"], &[(0, 1), (1, 2), (2, 3), (0, 3)], &[]);
+        let paths = data.shortest_paths(&ids(&[0]), &ids(&[3]), 1, true);
+        assert_eq!(names(&data, &paths), [["main", "c"]]);
+    }
+
+    #[test]
+    fn k_shortest_paths() {
+        let data = graph(&["main", "a", "b", "c"],
+                         &[(0, 1), (0, 2), (1, 3), (2, 3), (1, 2), (0, 3)],
+                         &[]);
+        let paths = data.shortest_paths(&ids(&[0]), &ids(&[3]), 10, true);
+        assert_eq!(names(&data, &paths),
+                   vec![vec!["main", "c"],
+                        vec!["main", "a", "c"],
+                        vec!["main", "b", "c"],
+                        vec!["main", "a", "b", "c"]]);
+    }
+
+    #[test]
+    fn no_path() {
+        let data = graph(&["main", "a", "b"], &[(0, 1)], &[]);
+        assert!(data.shortest_paths(&ids(&[0]), &ids(&[2]), 3, true).is_empty());
+        assert!(data.simple_paths(&ids(&[0]), &ids(&[2]), 3, true).is_empty());
+    }
+
+    #[test]
+    fn overlapping_from_and_to() {
+        // `path '*' foo`: foo is in from too, but a path needs a call.
+        let data = graph(&["main", "foo", "bar"], &[(0, 1), (0, 2)], &[]);
+        let all = ids(&[0, 1, 2]);
+        let paths = data.shortest_paths(&all, &ids(&[1]), 5, true);
+        assert_eq!(names(&data, &paths), [["main", "foo"]]);
+        let paths = data.simple_paths(&all, &ids(&[1]), 5, true);
+        assert_eq!(names(&data, &paths), [["main", "foo"]]);
+
+        // A start may be the end of a path from another start.
+        let data = graph(&["main", "a", "b"], &[(0, 1), (1, 2)], &[]);
+        let paths = data.shortest_paths(&ids(&[0, 1]), &ids(&[1, 2]), 5, true);
+        assert_eq!(names(&data, &paths), [["main", "a"], ["a", "b"]]);
+    }
+
+    #[test]
+    fn direct_recursion_path() {
+        let data = graph(&["main", "foo"], &[(0, 1), (1, 1)], &[]);
+        let paths = data.shortest_paths(&ids(&[1]), &ids(&[1]), 5, true);
+        assert_eq!(names(&data, &paths), [["foo", "foo"]]);
+        let paths = data.simple_paths(&ids(&[1]), &ids(&[1]), 5, true);
+        assert_eq!(names(&data, &paths), [["foo", "foo"]]);
+    }
+
+    #[test]
+    fn mutual_recursion_path() {
+        let data = graph(&["a", "b", "c"], &[(0, 1), (1, 0), (1, 2), (2, 0)], &[]);
+        let paths = data.shortest_paths(&ids(&[0]), &ids(&[0]), 5, true);
+        assert_eq!(names(&data, &paths), [vec!["a", "b", "a"], vec!["a", "b", "c", "a"]]);
+        let paths = data.simple_paths(&ids(&[0]), &ids(&[0]), 5, true);
+        assert_eq!(names(&data, &paths), [vec!["a", "b", "a"], vec!["a", "b", "c", "a"]]);
+    }
+
+    #[test]
+    fn simple_paths_max_len() {
+        let data = graph(&["main", "a", "b", "c"], &[(0, 1), (1, 2), (2, 3), (0, 3)], &[]);
+        let paths = data.simple_paths(&ids(&[0]), &ids(&[3]), 2, true);
+        assert_eq!(names(&data, &paths), [["main", "c"]]);
+        let paths = data.simple_paths(&ids(&[0]), &ids(&[3]), 3, true);
+        assert_eq!(names(&data, &paths), [vec!["main", "c"], vec!["main", "a", "b", "c"]]);
+    }
+
+    #[test]
+    fn paths_potential() {
+        let data = graph(&["main", "a"], &[], &[(0, 1)]);
+        let paths = data.shortest_paths(&ids(&[0]), &ids(&[1]), 1, true);
+        assert_eq!(paths, [[(id(0), id(1), CallKind::Potential)]]);
+        assert!(data.shortest_paths(&ids(&[0]), &ids(&[1]), 1, false).is_empty());
+    }
+
+    // Components as the names of their functions.
+    fn scc_names(data: &FnData, sccs: &[Vec<Id>]) -> Vec<Vec<String>> {
+        sccs.iter()
+            .map(|scc| scc.iter().map(|id| data.functions[id].name.clone()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn sccs() {
+        // a <-> b -> c <-> d, and e on its own.
+        let data = graph(&["a", "b", "c", "d", "e"], &[(0, 1), (1, 0), (1, 2), (2, 3), (3, 2)], &[]);
+        // Callees come before their callers.
+        assert_eq!(scc_names(&data, &data.sccs()), [vec!["c", "d"], vec!["a", "b"], vec!["e"]]);
+    }
+
+    #[test]
+    fn recursion() {
+        // main -> a <-> b, c calls itself, d doesn't, and e only potentially
+        // calls itself.
+        let data = graph(&["main", "a", "b", "c", "d", "e"],
+                         &[(0, 1), (1, 2), (2, 1), (0, 3), (3, 3), (0, 4)],
+                         &[(5, 5)]);
+        assert_eq!(scc_names(&data, &data.recursion()),
+                   [vec!["a", "b"], vec!["c"], vec!["e"]]);
+    }
+
+    #[test]
+    fn address_taken_is_not_recursion() {
+        // register stores a pointer to handler, which calls register.
+        let mut data = graph(&["register", "handler"], &[(1, 0)], &[]);
+        data.address_taken.entry((id(0), id(1))).or_default().add(site(0), false);
+        assert!(data.recursion().is_empty());
+        assert_eq!(data.sccs().len(), 2);
+    }
+
+    #[test]
+    fn long_chain_sccs() {
+        // Deep enough to overflow the stack if we recursed.
+        let n = 20_000;
+        let names: Vec<String> = (0..n).map(|i| format!("f{}", i)).collect();
+        let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        let mut calls: Vec<(u32, u32)> = (0..n - 1).map(|i| (i, i + 1)).collect();
+        calls.push((n - 1, 0));
+        let data = graph(&names, &calls, &[]);
+        let sccs = data.sccs();
+        assert_eq!(sccs.len(), 1);
+        assert_eq!(sccs[0].len(), n as usize);
+    }
+
+    #[test]
+    fn unsafe_reach() {
+        // vec calls the unsafe exchange_malloc implicitly (as for `vec![..]`),
+        // block calls helper in an unsafe block, and api calls wrapper, which
+        // calls the unsafe raw.
+        let mut data = graph(&["vec", "exchange_malloc", "block", "helper", "api", "wrapper", "raw"],
+                             &[(4, 5), (5, 6)],
+                             &[]);
+        for (i, node) in data.functions.iter_mut() {
+            node.exported = [0, 2, 4].contains(&i.index);
+            node.is_unsafe = [1, 6].contains(&i.index);
+        }
+        data.static_calls.entry((id(0), id(1))).or_default()
+            .add_call(site(1), CallSyntax::Implicit, false);
+        data.static_calls.entry((id(2), id(3))).or_default()
+            .add_call(site(3), CallSyntax::Direct, true);
+        // Each function, with where its path ends.
+        let reach: Vec<_> = data.unsafe_reach(true).into_iter()
+            .map(|(id, path)| {
+                let last = path.last().map_or(id, |&(_, to, _)| to);
+                (data.functions[&id].name.as_str(), data.functions[&last].name.as_str())
+            })
+            .collect();
+        assert_eq!(reach, [("block", "block"), ("api", "wrapper")]);
+    }
+
+    // The names of the functions in data, sorted.
+    fn fn_names(data: &FnData) -> Vec<&str> {
+        let mut names: Vec<&str> = data.functions.values().map(|n| n.name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    fn query(direction: Direction, pattern: &str, max_depth: Option<usize>, potential: bool)
+             -> Query {
+        Query { direction, pattern: Pattern::parse(pattern).unwrap(), max_depth, potential }
+    }
+
+    #[test]
+    fn query_callees() {
+        // main -> a -> b -> c, and a may call d.
+        let data = || graph(&["main", "a", "b", "c", "d"], &[(0, 1), (1, 2), (2, 3)], &[(1, 4)]);
+
+        let mut near = data();
+        assert!(near.query(&query(Direction::Callees, "main", Some(2), false)));
+        assert_eq!(fn_names(&near), ["a", "b", "main"]);
+        assert!(near.dynamic_calls.is_empty());
+
+        let mut all = data();
+        assert!(all.query(&query(Direction::Callees, "main", None, true)));
+        assert_eq!(fn_names(&all), ["a", "b", "c", "d", "main"]);
+        assert_eq!(all.dynamic_calls.len(), 1);
+
+        let mut none = data();
+        assert!(!none.query(&query(Direction::Callees, "nothing", None, true)));
+        assert!(none.functions.is_empty());
+    }
+
+    #[test]
+    fn query_callers() {
+        let data = || graph(&["main", "a", "b", "c", "d"], &[(0, 1), (1, 2), (2, 3)], &[(4, 3)]);
+
+        let mut near = data();
+        assert!(near.query(&query(Direction::Callers, "c", Some(1), false)));
+        assert_eq!(fn_names(&near), ["b", "c"]);
+
+        let mut potential = data();
+        assert!(potential.query(&query(Direction::Callers, "c", Some(1), true)));
+        assert_eq!(fn_names(&potential), ["b", "c", "d"]);
+    }
+
+    #[test]
+    fn parse_roots() {
+        let roots = Roots::parse("main,test").unwrap();
+        assert_eq!(roots, Roots {
+            main: true,
+            public: false,
+            tests: true,
+            exports: false,
+            trait_impls: false,
+        });
+        assert_eq!(Roots::parse(&Roots::NAMES.join(",")), Ok(Roots::all()));
+        assert!(!Roots::parse("").unwrap().contains(&node("test", 0, "main", false)));
+        assert_eq!(Roots::parse("main,bogus").unwrap_err(),
+                   "unknown root `bogus`, expected one of main, pub, test, exports, trait-impls");
+    }
+
+    #[test]
+    fn unreachable() {
+        // main calls used, which has a closure, and dead calls dead_callee.
+        // The const X is on its own, a test calls tested, and the trait impl
+        // method, the pub function, and the extern function call nothing.
+        let mut data = graph(&["main", "used", "closure", "dead", "dead_callee", "X", "test",
+                               "tested", "impl_method", "api", "extern_fn", "std_fn"],
+                             &[(0, 1), (3, 4), (6, 7)],
+                             &[]);
+        data.contains.entry((id(1), id(2))).or_default().add(site(1), false);
+        for (i, node) in data.functions.iter_mut() {
+            node.exported = false;
+            match i.index {
+                0 => node.entry = true,
+                2 => node.kind = FnKind::Closure,
+                5 => node.kind = FnKind::Const,
+                6 => node.test = true,
+                8 => node.kind = FnKind::TraitImplMethod,
+                9 => node.exported = true,
+                10 => node.abi = Some("C".to_owned()),
+                11 => node.external = true,
+                _ => {}
+            }
+        }
+        let unreachable = |roots: &str| -> Vec<&str> {
+            data.unreachable(&Roots::parse(roots).unwrap()).iter()
+                .map(|id| data.functions[id].name.as_str())
+                .collect()
+        };
+        assert_eq!(unreachable("main,pub,test,exports,trait-impls"), ["dead", "dead_callee"]);
+        assert_eq!(unreachable("main"),
+                   ["dead", "dead_callee", "test", "tested", "impl_method", "api", "extern_fn"]);
+        assert_eq!(unreachable(""),
+                   ["main", "used", "closure", "dead", "dead_callee", "test", "tested",
+                    "impl_method", "api", "extern_fn"]);
+    }
+
+    #[test]
+    fn aggregate() {
+        // m::a calls m::b, which calls n::c, and S::f (in an impl in m) calls
+        // m::a and may call n::c.
+        let data = || {
+            let mut data =
+                graph(&["m::a", "m::b", "n::c", "S::f"], &[(0, 1), (1, 2), (3, 0)], &[(3, 2)]);
+            data.functions.insert(id(3), FnNode {
+                name: "S::f".to_owned(),
+                kind: FnKind::InherentMethod,
+                module: "::m".to_owned(),
+                ..node("test", 0, "m::{impl#0}::f", false)
+            });
+            data
+        };
+
+        let mut modules = data();
+        modules.aggregate(Aggregation::Module);
+        assert_eq!(fn_names(&modules), ["test::m", "test::n"]);
+        assert_eq!(call_names(&modules),
+                   [("test::m".to_owned(), "test::m".to_owned(), CallKind::Definite),
+                    ("test::m".to_owned(), "test::n".to_owned(), CallKind::Definite),
+                    ("test::m".to_owned(), "test::n".to_owned(), CallKind::Potential)]);
+        assert!(modules.functions.values().all(|n| n.kind == FnKind::Module));
+        let edge = modules.edges().find(|&(from, to, kind)| {
+            from != to && kind == CallKind::Definite
+        }).unwrap();
+        assert_eq!(modules.call_sites(&edge).sites, [site(1)]);
+
+        let mut impls = data();
+        impls.aggregate(Aggregation::Impl);
+        assert_eq!(fn_names(&impls), ["S", "test::m", "test::n"]);
+        assert_eq!(call_names(&impls),
+                   [("S".to_owned(), "test::m".to_owned(), CallKind::Definite),
+                    ("S".to_owned(), "test::n".to_owned(), CallKind::Potential),
+                    ("test::m".to_owned(), "test::m".to_owned(), CallKind::Definite),
+                    ("test::m".to_owned(), "test::n".to_owned(), CallKind::Definite)]);
+    }
+
+    #[test]
+    fn collapse_external() {
+        // main calls two functions of std, and one of another crate.
+        let mut data = FnData::new("test");
+        let std_fn = |index| Id { krate: 1, index };
+        data.functions.insert(id(0), node("test", 0, "main", false));
+        data.functions.insert(std_fn(0), node("std", 1, "foo", true));
+        data.functions.insert(std_fn(1), node("std", 1, "bar", true));
+        data.functions.insert(Id { krate: 2, index: 0 }, node("other", 2, "baz", true));
+        data.static_calls.entry((id(0), std_fn(0))).or_default().add(site(1), false);
+        data.static_calls.entry((id(0), std_fn(1))).or_default().add(site(2), false);
+        data.static_calls.entry((id(0), Id { krate: 2, index: 0 })).or_default().add(site(3), false);
+
+        data.collapse_external();
+        assert_eq!(fn_names(&data), ["main", "other", "std"]);
+        let std_node = &data.functions[&Id { krate: 1, index: u32::MAX }];
+        assert_eq!(std_node.kind, FnKind::Crate);
+        assert!(std_node.external);
+        let sites = &data.static_calls[&(id(0), Id { krate: 1, index: u32::MAX })];
+        assert_eq!(sites.sites.len(), 2);
+        assert!(sites.sites.contains(&site(1)) && sites.sites.contains(&site(2)));
+        assert_eq!(sites.count, 2);
+    }
+
+    // The calls in data, by the names of the functions.
+    fn call_names(data: &FnData) -> Vec<(String, String, CallKind)> {
+        let mut calls: Vec<_> = data.edges()
+            .map(|(from, to, kind)| {
+                (data.functions[&from].name.clone(), data.functions[&to].name.clone(), kind)
+            })
+            .collect();
+        calls.sort();
+        calls
+    }
+
+    // A package's lib and bin have the same crate name, but are different
+    // crates. The bin calls the lib's libfn, which is external to it.
+    fn lib_and_bin() -> (FnData, FnData) {
+        let mut lib = FnData::new("pkg");
+        lib.functions.insert(id(0), node("pkg", 1, "libfn", false));
+        lib.functions.insert(id(1), node("pkg", 1, "inner", false));
+        lib.static_calls.entry((id(0), id(1))).or_default().add(site(2), false);
+
+        let mut bin = FnData::new("pkg");
+        let libfn = Id { krate: 5, index: 0 };
+        bin.functions.insert(id(0), node("pkg", 2, "main", false));
+        bin.functions.insert(libfn, node("pkg", 1, "libfn", true));
+        bin.static_calls.entry((id(0), libfn)).or_default().add(site(7), false);
+        (lib, bin)
+    }
+
+    #[test]
+    fn merge_lib_and_bin() {
+        let (lib, bin) = lib_and_bin();
+        let (lib2, bin2) = lib_and_bin();
+        for graphs in [vec![lib, bin], vec![bin2, lib2]] {
+            let merged = FnData::merge(graphs, "merged");
+            assert_eq!(merged.functions.len(), 3);
+            let libfn = merged.functions.values().find(|n| n.name == "libfn").unwrap();
+            assert!(!libfn.external);
+            assert_eq!(call_names(&merged),
+                       [("libfn".to_owned(), "inner".to_owned(), CallKind::Definite),
+                        ("main".to_owned(), "libfn".to_owned(), CallKind::Definite)]);
+        }
+    }
+
+    #[test]
+    fn merge_combines_call_sites() {
+        let a = graph(&["main", "f"], &[(0, 1)], &[]);
+        let mut b = graph(&["main", "f"], &[], &[(0, 1)]);
+        b.static_calls.entry((id(0), id(1))).or_default().add(site(9), false);
+        let merged = FnData::merge(vec![a, b], "merged");
+        assert_eq!(merged.functions.len(), 2);
+        let edge = merged.edges().find(|e| e.2 == CallKind::Definite).unwrap();
+        assert_eq!(merged.call_sites(&edge).sites, [site(0), site(9)]);
+        assert_eq!(merged.call_sites(&edge).count, 2);
+        assert_eq!(merged.dynamic_calls.len(), 1);
+    }
+
+    #[test]
+    fn merge_ids_follow_old_ids() {
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let merged = FnData::merge(vec![graph(&names, &[], &[])], "merged");
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(merged.functions[&id(i as u32)].name, *name);
+        }
+    }
+
+    #[test]
+    fn diff() {
+        // b is removed and d added, main stops calling c and starts calling d,
+        // and a's call to c doesn't change.
+        let old = graph(&["main", "a", "b", "c"], &[(0, 1), (0, 2), (0, 3), (1, 3)], &[]);
+        let new = graph(&["main", "a", "c", "d"], &[(0, 1), (0, 3), (1, 2)], &[]);
+        let diff = FnData::diff(old, new);
+        let data = &diff.data;
+        let fn_names = |ids: &[Id]| -> Vec<String> {
+            ids.iter().map(|id| data.functions[id].name.clone()).collect()
+        };
+        let edge_names = |edges: &[Edge]| -> Vec<(String, String)> {
+            let mut names: Vec<_> = edges.iter()
+                .map(|(from, to, _)| (data.functions[from].name.clone(),
+                                      data.functions[to].name.clone()))
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(data.functions.len(), 5);
+        assert_eq!(fn_names(&diff.added_fns), ["d"]);
+        assert_eq!(fn_names(&diff.removed_fns), ["b"]);
+        assert_eq!(edge_names(&diff.added_calls), [("main".to_owned(), "d".to_owned())]);
+        assert_eq!(edge_names(&diff.removed_calls),
+                   [("main".to_owned(), "b".to_owned()), ("main".to_owned(), "c".to_owned())]);
+        // Unchanged calls are only there once.
+        assert_eq!(data.static_calls.len(), 5);
+    }
 }
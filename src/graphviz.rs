@@ -8,88 +8,181 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use super::fndata::FnData;
-
-use rustc_graphviz as graphviz;
-use rustc_graphviz::{Labeller, GraphWalk, Style};
-
-use std::iter::FromIterator;
-
-use rls_data::Id;
+use super::fndata::{CallKind, Edge, FnData, Id};
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+// Options for how we draw the graph.
+#[derive(Clone, Debug, Default)]
+pub struct DotOptions {
+    // Put each recursive SCC (see FnData::recursion) in a red cluster, and
+    // draw the calls within it in red.
+    pub cluster_sccs: bool,
+    // Put the functions of our crate(s) in a cluster for each module. Functions
+    // in recursive SCCs stay in the SCC's cluster if cluster_sccs is set.
+    pub cluster_modules: bool,
+}
 
+// Colors for particular functions and calls, e.g., to show a diff. These take
+// precedence over any colors from the options.
+#[derive(Clone, Debug, Default)]
+pub struct Colors {
+    pub nodes: HashMap<Id, &'static str>,
+    pub edges: HashMap<Edge, &'static str>,
+}
 
 // Graphviz interaction.
 //
-// We use NodeIds to identify nodes in the graph to Graphviz. We label them by
-// looking up the name for the id in self.functions. Edges are the union of
-// static and dynamic calls. We don't label edges, but potential calls due to
-// dynamic dispatch get dotted edges.
+// We use Ids to identify nodes in the graph to Graphviz. We label them by
+// looking up the name for the id in data.functions. Edges are the union of
+// static and dynamic calls. Edges are labelled with the number of calls if
+// there is more than one, and have a tooltip listing the call sites. Potential
+// calls due to dynamic dispatch get dotted edges, and calls in unsafe blocks
+// get bold edges. Edges from a function to the closures, consts, etc. defined in
+// it are dashed. Edges from a function to the functions whose address it takes
+// are dotted with a circle for an arrowhead. Unsafe functions have a red
+// border. Functions from other crates are boxes, grouped into a cluster per
+// crate.
+//
+// Options may add more clusters, and colors.
+//
+// We write the dot file ourselves, rustc_graphviz doesn't support clusters.
 //
-// Invariants: all edges must be beween nodes which are in self.functions.
+// Invariants: all edges must be beween nodes which are in data.functions.
 //             post_process must have been called (i.e., no decls left in the graph)
-
-// Whether a call certainly happens (e.g., static dispatch) or only might happen
-// (e.g., all possible receiving methods of dynamic dispatch).
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub enum CallKind {
-    Definite,
-    Potential,
+pub fn render<W: Write>(data: &FnData, options: &DotOptions, out: &mut W) -> io::Result<()> {
+    render_colored(data, options, &Colors::default(), out)
 }
 
-// An edge in the callgraph, only used with graphviz.
-pub type Edge = (Id, Id, CallKind);
-
-// Issues ids, labels, and styles for graphviz.
-impl<'a> Labeller<'a> for FnData {
-    type Node = Id;
-    type Edge = Edge;
-
-    fn graph_id(&'a self) -> graphviz::Id<'a> {
-        graphviz::Id::new(format!("Callgraph_for_{}", self.crate_name)).unwrap()
+pub fn render_colored<W: Write>(data: &FnData,
+                                options: &DotOptions,
+                                colors: &Colors,
+                                out: &mut W)
+                                -> io::Result<()> {
+    writeln!(out, "digraph Callgraph_for_{} {{", data.crate_name)?;
+
+    // Which recursive SCC each function is in, if any.
+    let mut scc_of: HashMap<Id, usize> = HashMap::new();
+    let mut colors = colors.clone();
+    if options.cluster_sccs {
+        for (i, scc) in data.recursion().into_iter().enumerate() {
+            scc_of.extend(scc.into_iter().map(|id| (id, i)));
+        }
+        for edge in data.edges() {
+            let (from, to, _) = edge;
+            if scc_of.contains_key(&from) && scc_of.get(&from) == scc_of.get(&to) {
+                colors.edges.entry(edge).or_insert("red");
+            }
+        }
     }
 
-    fn node_id(&'a self, n: &Id) -> graphviz::Id<'a> {
-        graphviz::Id::new(format!("n_{}_{}", n.krate, n.index)).unwrap()
+    let mut ids: Vec<&Id> = data.functions.keys().collect();
+    ids.sort();
+
+    let mut clusters: BTreeMap<&str, Vec<&Id>> = BTreeMap::new();
+    let mut scc_clusters: BTreeMap<usize, Vec<&Id>> = BTreeMap::new();
+    let mut module_clusters: BTreeMap<(&str, &str), Vec<&Id>> = BTreeMap::new();
+    for id in ids {
+        let node = &data.functions[id];
+        if node.external {
+            clusters.entry(&*node.path.krate).or_default().push(id);
+        } else if let Some(&scc) = scc_of.get(id) {
+            scc_clusters.entry(scc).or_default().push(id);
+        } else if options.cluster_modules {
+            module_clusters.entry((&node.path.krate, node.module_path())).or_default().push(id);
+        } else {
+            write_node(data, &colors, id, "    ", out)?;
+        }
     }
-
-    fn node_label(&'a self, n: &Id) -> graphviz::LabelText<'a> {
-        // To find the label, we just lookup the function name.
-        graphviz::LabelText::label(&*self.functions[n])
+    for (i, ((krate, module), ids)) in module_clusters.into_iter().enumerate() {
+        writeln!(out, "    subgraph cluster_mod_{} {{", i)?;
+        writeln!(out, "        label={};", quote(&format!("{}{}", krate, module)))?;
+        for id in ids {
+            write_node(data, &colors, id, "        ", out)?;
+        }
+        writeln!(out, "    }}")?;
     }
-
-    fn edge_style(&'a self, e: &Edge) -> Style {
-        match e.2 {
-            CallKind::Definite => Style::None,
-            CallKind::Potential => Style::Dotted,
+    for (scc, ids) in scc_clusters {
+        writeln!(out, "    subgraph cluster_scc_{} {{", scc)?;
+        writeln!(out, "        label=\"recursion\";")?;
+        writeln!(out, "        color=\"red\";")?;
+        for id in ids {
+            write_node(data, &colors, id, "        ", out)?;
         }
+        writeln!(out, "    }}")?;
     }
-}
-
-// Drives the graphviz visualisation.
-impl<'a> GraphWalk<'a> for FnData {
-    type Node = Id;
-    type Edge = Edge;
-
-    fn nodes(&'a self) -> graphviz::Nodes<'a, Id> {
-        graphviz::Nodes::from_iter(self.functions.keys().cloned())
+    for (krate, ids) in clusters {
+        writeln!(out, "    subgraph cluster_{} {{", krate)?;
+        writeln!(out, "        label={};", quote(krate))?;
+        for id in ids {
+            write_node(data, &colors, id, "        ", out)?;
+        }
+        writeln!(out, "    }}")?;
     }
 
-    fn edges(&'a self) -> graphviz::Edges<'a, Edge> {
-        let static_iter = self.static_calls.iter().map(|&(ref f, ref t)| (f.clone(),
-                                                                          t.clone(),
-                                                                          CallKind::Definite));
-        let dyn_iter = self.dynamic_calls.iter().map(|&(ref f, ref t)| (f.clone(),
-                                                                        t.clone(),
-                                                                        CallKind::Potential));
-        graphviz::Edges::from_iter(static_iter.chain(dyn_iter))
+    let mut edges: Vec<_> = data.edges().collect();
+    edges.sort_by_key(|&(from, to, _)| (from, to));
+    for edge in edges {
+        let (from, to, kind) = edge;
+        let sites = data.call_sites(&edge);
+        let mut styles = vec![];
+        match kind {
+            CallKind::Definite => {}
+            CallKind::Potential => styles.push("dotted"),
+            CallKind::Contains => styles.push("dashed"),
+            CallKind::AddressTaken => styles.push("dotted"),
+        }
+        if !sites.unsafe_sites.is_empty() {
+            styles.push("bold");
+        }
+        let style = if styles.is_empty() {
+            String::new()
+        } else {
+            format!("[style={}]", quote(&styles.join(",")))
+        };
+        let label = if sites.count > 1 {
+            format!("[label={}]", quote(&sites.count.to_string()))
+        } else {
+            String::new()
+        };
+        let color = match colors.edges.get(&edge) {
+            Some(color) => format!("[color={}]", quote(color)),
+            None => String::new(),
+        };
+        let arrow = if kind == CallKind::AddressTaken { "[arrowhead=\"odot\"]" } else { "" };
+        let tooltip: Vec<String> = sites.sites.iter().map(|s| s.to_string()).collect();
+        writeln!(out, "    {} -> {}{}{}{}{}[tooltip={}];",
+                 node_id(&from), node_id(&to), style, arrow, color, label,
+                 quote(&tooltip.join("\n")))?;
     }
 
-    fn source(&'a self, &(from, _, _): &Edge) -> Id {
-        from
-    }
+    writeln!(out, "}}")
+}
 
-    fn target(&'a self, &(_, to, _): &Edge) -> Id {
-        to
-    }
+fn write_node<W: Write>(data: &FnData,
+                        colors: &Colors,
+                        id: &Id,
+                        indent: &str,
+                        out: &mut W)
+                        -> io::Result<()> {
+    // To find the label, we just lookup the function name.
+    let node = &data.functions[id];
+    let shape = if node.external { "[shape=\"box\"]" } else { "" };
+    let color = match colors.nodes.get(id) {
+        Some(color) => format!("[color={0}][fontcolor={0}]", quote(color)),
+        None if node.is_unsafe => "[color=\"red\"]".to_owned(),
+        None => String::new(),
+    };
+    writeln!(out, "{}{}[label={}]{}{};", indent, node_id(id), quote(&node.name), shape, color)
 }
 
+fn node_id(n: &Id) -> String {
+    format!("n_{}_{}", n.krate, n.index)
+}
+
+// Quote s as a dot string.
+fn quote(s: &str) -> String {
+    let s = s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("\"{}\"", s)
+}
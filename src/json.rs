@@ -0,0 +1,187 @@
+// Reading and writing FnData as JSON.
+//
+// The format is versioned by SCHEMA_VERSION, bump it whenever the format
+// changes. We only read files with the current version.
+//
+// A graph looks like:
+//
+// {
+//   "version": 1,
+//   "crate_name": "foo",
+//   "nodes": [
+//     { "id": { "krate": 0, "index": 4 }, "name": "foo",
+//       "path": { "krate": "foo", "disambiguator": 123, "def_path": "::foo" },
+//       "external": false, "kind": "free_fn",
+//       "span": { "file": "foo.rs", "line": 11, "column": 1 },
+//       "visibility": "private", "unsafe": false, "async": false,
+//       "const": false, "abi": null, "generics": [], "entry": false,
+//       "test": false, "exported": false, "no_mangle": false, "panic": false,
+//       "alloc": false, "no_alloc": false },
+//     ...
+//   ],
+//   "edges": [
+//     { "from": { "krate": 0, "index": 5 }, "to": { "krate": 0, "index": 4 },
+//       "kind": "definite", "count": 1,
+//       "call_sites": [ { "file": "foo.rs", "line": 17, "column": 5 } ],
+//       "unsafe_sites": [], "method_sites": [], "implicit_sites": [] },
+//     ...
+//   ]
+// }
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::fndata::{CallKind, CallSites, FnData, FnNode, Id, SourceLoc};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Graph {
+    version: u32,
+    crate_name: String,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Node {
+    id: Id,
+    #[serde(flatten)]
+    node: FnNode,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Edge {
+    from: Id,
+    to: Id,
+    kind: CallKind,
+    // The number of calls, see CallSites.
+    count: usize,
+    call_sites: Vec<SourceLoc>,
+    // The call sites which are in unsafe blocks.
+    unsafe_sites: Vec<SourceLoc>,
+    // The call sites which are method calls or implicit, see CallSyntax.
+    method_sites: Vec<SourceLoc>,
+    implicit_sites: Vec<SourceLoc>,
+}
+
+pub fn write<W: Write>(data: &FnData, out: &mut W) -> io::Result<()> {
+    let mut nodes: Vec<Node> = data.functions.iter()
+        .map(|(id, node)| Node { id: *id, node: node.clone() })
+        .collect();
+    nodes.sort_by_key(|n| n.id);
+
+    let mut edges: Vec<Edge> = data.edges().map(|edge| {
+        let sites = data.call_sites(&edge);
+        Edge {
+            from: edge.0,
+            to: edge.1,
+            kind: edge.2,
+            count: sites.count,
+            call_sites: sites.sites.clone(),
+            unsafe_sites: sites.unsafe_sites.clone(),
+            method_sites: sites.method_sites.clone(),
+            implicit_sites: sites.implicit_sites.clone(),
+        }
+    }).collect();
+    edges.sort_by_key(|e| (e.from, e.to));
+
+    let graph = Graph {
+        version: SCHEMA_VERSION,
+        crate_name: data.crate_name.clone(),
+        nodes,
+        edges,
+    };
+    serde_json::to_writer_pretty(&mut *out, &graph)?;
+    writeln!(out)
+}
+
+pub fn read<R: Read>(input: R) -> io::Result<FnData> {
+    let graph: Graph = serde_json::from_reader(input)?;
+    if graph.version != SCHEMA_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported callgraph version {}, expected {}", graph.version, SCHEMA_VERSION),
+        ));
+    }
+
+    let mut data = FnData::new(&graph.crate_name);
+    data.functions = HashMap::with_capacity(graph.nodes.len());
+    for node in graph.nodes {
+        data.functions.insert(node.id, node.node);
+    }
+    for edge in graph.edges {
+        for id in [edge.from, edge.to] {
+            if !data.functions.contains_key(&id) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("edge to or from unknown node {}:{}", id.krate, id.index),
+                ));
+            }
+        }
+        let sites = CallSites {
+            sites: edge.call_sites,
+            count: edge.count,
+            unsafe_sites: edge.unsafe_sites,
+            method_sites: edge.method_sites,
+            implicit_sites: edge.implicit_sites,
+        };
+        data.calls_mut(edge.kind).insert((edge.from, edge.to), sites);
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fndata::tests::{graph, id, site};
+    use crate::fndata::CallSyntax;
+
+    #[test]
+    fn round_trip() {
+        let mut data = graph(&["main", "a", "b"], &[(0, 1)], &[(1, 2)]);
+        let sites = data.static_calls.get_mut(&(id(0), id(1))).unwrap();
+        sites.add_call(site(3), CallSyntax::Method, true);
+        sites.add_call(site(3), CallSyntax::Implicit, false);
+        data.contains.entry((id(0), id(2))).or_default().add(site(5), false);
+        data.address_taken.entry((id(1), id(0))).or_default().add(site(6), false);
+
+        let mut out = vec![];
+        write(&data, &mut out).unwrap();
+        let read = read(&out[..]).unwrap();
+        assert_eq!(read.crate_name, data.crate_name);
+        assert_eq!(read.functions, data.functions);
+        for kind in CallKind::ALL {
+            assert_eq!(read.calls(kind), data.calls(kind));
+        }
+    }
+
+    #[test]
+    fn wrong_version() {
+        let data = graph(&["main"], &[], &[]);
+        let mut out = vec![];
+        write(&data, &mut out).unwrap();
+        let json = String::from_utf8(out).unwrap().replace(
+            &format!("\"version\": {}", SCHEMA_VERSION),
+            &format!("\"version\": {}", SCHEMA_VERSION + 1),
+        );
+        let err = read(json.as_bytes()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("unsupported callgraph version"));
+    }
+
+    #[test]
+    fn unknown_node() {
+        let data = graph(&["main", "a"], &[(0, 1)], &[]);
+        let mut out = vec![];
+        write(&data, &mut out).unwrap();
+        let mut json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        json["edges"][0]["to"]["index"] = 7.into();
+        let err = read(json.to_string().as_bytes()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("unknown node 0:7"));
+    }
+}
@@ -1,124 +1,683 @@
 #![feature(rustc_private)]
 
-extern crate rls_data;
-extern crate graphviz as rustc_graphviz;
-extern crate rustc;
-extern crate rustc_data_structures;
 extern crate rustc_driver;
-extern crate rustc_save_analysis;
-extern crate syntax;
-extern crate syntax_pos;
+extern crate rustc_hir;
+extern crate rustc_interface;
+extern crate rustc_middle;
+extern crate rustc_session;
+extern crate rustc_span;
+extern crate rustc_target;
 
-use rustc::session::Session;
-use rustc::session::config::Input;
-use rustc_driver::{driver, CompilerCalls, Compilation, getopts::Matches};
-use rustc_save_analysis::{SaveContext, SaveHandler};
-use rustc_save_analysis as save;
-use syntax::{ast,visit};
+use rustc_driver::Compilation;
+use rustc_hir::def_id::LOCAL_CRATE;
+use rustc_interface::{interface, Queries};
+use rustc_middle::ty::TyCtxt;
+use rustc_session::config::CrateType;
+use rustc_session::filesearch;
+use rustc_span::Symbol;
 
 use std::env;
-use std::process::Command;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
 
 // Where all the work is done
 mod visitor;
+mod mir;
+mod mono;
 
 mod fndata;
 mod graphviz;
+mod json;
+mod pattern;
+mod report;
 
-pub const SKIP_UNCONNECTED_FNS: bool = false;
+pub use crate::fndata::{Aggregation, Direction, FnData, GraphDiff, PathSearch, Query, Roots};
+pub use crate::graphviz::DotOptions;
+pub use crate::pattern::Pattern;
 
-// Coordinates the compiler, doesn't need any state for callgraphs.
-struct CallGraphCalls;
+// The formats we can write a callgraph in.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    // A graphviz dot file.
+    Dot,
+    // The plain text listing produced by FnData::dump.
+    Text,
+    // JSON which can be read back in by FnData::load, see json.rs.
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "dot" => Some(OutputFormat::Dot),
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            _ => None,
+        }
+    }
+
+    // File extension used for the default output path.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Dot => "dot",
+            OutputFormat::Text => "txt",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+// Reports we can write instead of a graph.
+#[derive(Clone, Debug)]
+pub enum Report {
+    // Functions which are unreachable from the roots, see FnData::unreachable.
+    DeadCode(Roots),
+    // Direct and mutual recursion, see FnData::recursion.
+    Recursion,
+    // Paths from the functions matching one pattern to those matching another.
+    Paths {
+        from: Pattern,
+        to: Pattern,
+        search: PathSearch,
+        // Whether to follow Potential calls.
+        potential: bool,
+    },
+    // Safe exported functions which can reach unsafe code, see
+    // FnData::unsafe_reach. The flag says whether to follow Potential calls.
+    Unsafe(bool),
+    // Functions which can panic, see FnData::panic_reach. The flag says
+    // whether to follow Potential calls.
+    Panics(bool),
+    // Functions which must not allocate but can, see FnData::alloc_reach.
+    NoAlloc {
+        // Functions to check as well as those marked `#[callgraph::no_alloc]`.
+        patterns: Vec<Pattern>,
+        potential: bool,
+    },
+}
+
+// Options for a run of the tool, everything which is not passed on to rustc.
+#[derive(Clone, Debug)]
+pub struct Config {
+    // Where to write the graph, `-` means stdout. If None, we write to
+    // `<crate_name>-<kind>.<ext>` in output_dir, where kind is the kind of
+    // target (`lib`, `bin`, `proc-macro`, or `test`), since a package's targets
+    // may share a crate name.
+    pub output: Option<PathBuf>,
+    // Defaults to the current directory.
+    pub output_dir: Option<PathBuf>,
+    pub format: OutputFormat,
+    // Drop functions which neither call nor are called by any other function.
+    pub skip_unconnected_fns: bool,
+    // Show each other crate as a single node, rather than a node per function.
+    pub collapse_external: bool,
+    // Show a node per module or impl, rather than per function.
+    pub aggregate: Option<Aggregation>,
+    // Also dump the collected information to stdout.
+    pub dump: bool,
+    // Graph monomorphised instances reachable from the crate's roots, rather
+    // than functions as written.
+    pub mono: bool,
+    // How to draw the graph when the format is dot.
+    pub dot_options: DotOptions,
+    // Read graphs saved as JSON rather than running the compiler. If there is
+    // more than one, they are merged.
+    pub load: Vec<PathBuf>,
+    // Compare two saved graphs, old and new, from the `diff` subcommand.
+    pub diff: Option<(PathBuf, PathBuf)>,
+    // Only output the functions matching include (if it's not empty), and not
+    // matching exclude. If keep_boundary is set, we keep the functions which
+    // call or are called by those, see FnData::filter.
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+    pub keep_boundary: bool,
+    // Only output the functions reachable from those matching a pattern, from
+    // the `callers` and `callees` subcommands.
+    pub query: Option<Query>,
+    // Write a report rather than a graph, from the `dead-code`, `recursion`,
+    // `unsafe`, `panics`, `no-alloc`, and `path` subcommands.
+    // Reports are written to stdout unless there is an output path.
+    pub report: Option<Report>,
+    // Stop the compiler once we have the callgraph. When we are run by Cargo
+    // the crate must be compiled as usual so its dependents can be built.
+    pub stop_after_analysis: bool,
+    // Environment variables to record in the crate's dep-info, so Cargo
+    // compiles the crate again (rather than finding it fresh) when they change.
+    pub env_deps: Vec<String>,
+}
+
+// The environment variable cargo-callgraph uses to pass our options to the
+// callgraph binary when it is run as a rustc wrapper. Options are separated by
+// WRAPPER_ARGS_SEP.
+pub const WRAPPER_ARGS_ENV: &str = "CALLGRAPH_ARGS";
+pub const WRAPPER_ARGS_SEP: char = '\x1f';
+// An environment variable which cargo-callgraph sets to a different value for
+// each run. The crates we analyse depend on it (see Config::env_deps), so
+// Cargo runs us on them every time, not only when their sources change.
+pub const WRAPPER_RUN_ENV: &str = "CALLGRAPH_RUN";
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            output: None,
+            output_dir: None,
+            format: OutputFormat::Dot,
+            skip_unconnected_fns: false,
+            collapse_external: false,
+            aggregate: None,
+            dump: false,
+            mono: false,
+            dot_options: DotOptions::default(),
+            load: vec![],
+            diff: None,
+            include: vec![],
+            exclude: vec![],
+            keep_boundary: false,
+            query: None,
+            report: None,
+            stop_after_analysis: true,
+            env_deps: vec![],
+        }
+    }
+}
+
+impl Config {
+    // Parse our command line options (not including any for rustc). These may
+    // start with a subcommand, `callers PATTERN`, `callees PATTERN`,
+    // `dead-code`, `recursion`, `unsafe`, `panics`, `no-alloc`, `path FROM TO`,
+    // or `diff OLD NEW`.
+    pub fn parse(opts: &[String]) -> Result<Config, String> {
+        let mut config = Config::default();
+
+        let mut opts = opts.iter().peekable();
+        let direction = match opts.peek().map(|o| &***o) {
+            Some("callers") => Some(Direction::Callers),
+            Some("callees") => Some(Direction::Callees),
+            _ => None,
+        };
+        if let Some(direction) = direction {
+            let cmd = opts.next().unwrap();
+            let pattern = opts.next().ok_or_else(|| format!("missing pattern for {}", cmd))?;
+            config.query = Some(Query {
+                direction,
+                pattern: Pattern::parse(pattern)?,
+                max_depth: None,
+                potential: true,
+            });
+        } else if opts.peek().map_or(false, |o| *o == "diff") {
+            opts.next();
+            let old = opts.next().ok_or("missing OLD graph for diff")?;
+            let new = opts.next().ok_or("missing NEW graph for diff")?;
+            config.diff = Some((PathBuf::from(old), PathBuf::from(new)));
+        } else if opts.peek().map_or(false, |o| *o == "path") {
+            opts.next();
+            let from = opts.next().ok_or("missing FROM pattern for path")?;
+            let to = opts.next().ok_or("missing TO pattern for path")?;
+            config.report = Some(Report::Paths {
+                from: Pattern::parse(from)?,
+                to: Pattern::parse(to)?,
+                search: PathSearch::Shortest(1),
+                potential: true,
+            });
+        } else {
+            config.report = match opts.peek().map(|o| &***o) {
+                Some("dead-code") => Some(Report::DeadCode(Roots::all())),
+                Some("recursion") => Some(Report::Recursion),
+                Some("unsafe") => Some(Report::Unsafe(true)),
+                Some("panics") => Some(Report::Panics(true)),
+                Some("no-alloc") => Some(Report::NoAlloc { patterns: vec![], potential: true }),
+                _ => None,
+            };
+            if config.report.is_some() {
+                opts.next();
+            }
+        }
+
+        while let Some(opt) = opts.next() {
+            match &**opt {
+                "-o" | "--output" => {
+                    let path = opts.next().ok_or("missing argument to --output")?;
+                    config.output = Some(PathBuf::from(path));
+                }
+                "--output-dir" => {
+                    let path = opts.next().ok_or("missing argument to --output-dir")?;
+                    config.output_dir = Some(PathBuf::from(path));
+                }
+                "--load" => {
+                    let path = opts.next().ok_or("missing argument to --load")?;
+                    config.load.push(PathBuf::from(path));
+                }
+                "-f" | "--format" => {
+                    let name = opts.next().ok_or("missing argument to --format")?;
+                    config.format = OutputFormat::from_name(name)
+                        .ok_or_else(|| format!("unknown format `{}`", name))?;
+                }
+                "--skip-unconnected" => config.skip_unconnected_fns = true,
+                "--collapse-external" => config.collapse_external = true,
+                "--aggregate" => {
+                    let level = opts.next().ok_or("missing argument to --aggregate")?;
+                    config.aggregate = match &**level {
+                        "module" => Some(Aggregation::Module),
+                        "impl" => Some(Aggregation::Impl),
+                        _ => return Err(format!("unknown aggregation level `{}`", level)),
+                    };
+                }
+                "--cluster-modules" => config.dot_options.cluster_modules = true,
+                "--include" => {
+                    let pattern = opts.next().ok_or("missing argument to --include")?;
+                    config.include.push(Pattern::parse(pattern)?);
+                }
+                "--exclude" => {
+                    let pattern = opts.next().ok_or("missing argument to --exclude")?;
+                    config.exclude.push(Pattern::parse(pattern)?);
+                }
+                "--keep-boundary" => config.keep_boundary = true,
+                "--dump" => config.dump = true,
+                "--mono" => config.mono = true,
+                "--cluster-sccs" => config.dot_options.cluster_sccs = true,
+                "--depth" => {
+                    let depth = parse_number(opt, opts.next())?;
+                    config.query_mut(opt)?.max_depth = Some(depth);
+                }
+                "--definite-only" => {
+                    match (&mut config.query, &mut config.report) {
+                        (Some(ref mut query), _) => query.potential = false,
+                        (_, Some(Report::Paths { ref mut potential, .. })) => *potential = false,
+                        (_, Some(Report::Unsafe(ref mut potential)))
+                        | (_, Some(Report::Panics(ref mut potential)))
+                        | (_, Some(Report::NoAlloc { ref mut potential, .. })) => {
+                            *potential = false
+                        }
+                        _ => return Err("`--definite-only` can only be used with callers, \
+                                         callees, path, unsafe, panics, or no-alloc".to_owned()),
+                    }
+                }
+                "--shortest" | "--all" => {
+                    let n = parse_number(opt, opts.next())?;
+                    match config.report {
+                        Some(Report::Paths { ref mut search, .. }) => {
+                            *search = if opt == "--all" {
+                                PathSearch::All(n)
+                            } else {
+                                PathSearch::Shortest(n)
+                            };
+                        }
+                        _ => return Err(format!("`{}` can only be used with path", opt)),
+                    }
+                }
+                "--roots" => {
+                    let list = opts.next().ok_or("missing argument to --roots")?;
+                    match config.report {
+                        Some(Report::DeadCode(ref mut roots)) => *roots = Roots::parse(list)?,
+                        _ => return Err("`--roots` can only be used with dead-code".to_owned()),
+                    }
+                }
+                "--list" => {
+                    let path = opts.next().ok_or("missing argument to --list")?;
+                    match config.report {
+                        Some(Report::NoAlloc { ref mut patterns, .. }) => {
+                            patterns.extend(read_patterns(path)?)
+                        }
+                        _ => return Err("`--list` can only be used with no-alloc".to_owned()),
+                    }
+                }
+                _ => return Err(format!("unknown option `{}`", opt)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn query_mut(&mut self, opt: &str) -> Result<&mut Query, String> {
+        self.query.as_mut()
+            .ok_or_else(|| format!("`{}` can only be used with callers or callees", opt))
+    }
+
+    // Write data out as requested by this config. name is the default output
+    // file's name, without the extension.
+    pub fn emit(&self, mut data: FnData, name: &str) -> io::Result<()> {
+        if let Some(ref report) = self.report {
+            return match self.output {
+                Some(ref path) if path.as_os_str() != "-" => {
+                    self.write_report(report, &data, &mut File::create(path)?)
+                }
+                _ => self.write_report(report, &data, &mut io::stdout().lock()),
+            };
+        }
+
+        if !self.include.is_empty() || !self.exclude.is_empty() {
+            data.filter(&self.include, &self.exclude, self.keep_boundary);
+        }
+        if let Some(ref query) = self.query {
+            if !data.query(query) {
+                eprintln!("warning: no function in {} matches `{}`",
+                          data.crate_name, query.pattern);
+            }
+        }
+        if self.skip_unconnected_fns {
+            data.remove_unconnected();
+        }
+        if let Some(level) = self.aggregate {
+            data.aggregate(level);
+        }
+        if self.collapse_external {
+            data.collapse_external();
+        }
+
+        if self.dump {
+            data.dump(&mut io::stdout())?;
+        }
+
+        let path = self.output_path(name);
+        if path.as_os_str() == "-" {
+            let stdout = io::stdout();
+            self.write(&data, &mut stdout.lock())
+        } else {
+            self.write(&data, &mut File::create(&path)?)
+        }
+    }
+
+    // Write diff out as requested by this config. Queries, reports, and the
+    // options which remove functions from the graph don't apply to diffs.
+    pub fn emit_diff(&self, diff: &GraphDiff) -> io::Result<()> {
+        let path = self.output_path(&diff.data.crate_name);
+        if path.as_os_str() == "-" {
+            let stdout = io::stdout();
+            self.write_diff(diff, &mut stdout.lock())
+        } else {
+            self.write_diff(diff, &mut File::create(&path)?)
+        }
+    }
+
+    // Where to write the output called name.
+    fn output_path(&self, name: &str) -> PathBuf {
+        self.output.clone().unwrap_or_else(|| {
+            let file = format!("{}.{}", name, self.format.extension());
+            match self.output_dir {
+                Some(ref dir) => dir.join(file),
+                None => PathBuf::from(file),
+            }
+        })
+    }
+
+    // The text format is a list of changes (see report::diff), and JSON has
+    // everything in either graph.
+    fn write_diff<W: Write>(&self, diff: &GraphDiff, out: &mut W) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Dot => diff.dot(&self.dot_options, out),
+            OutputFormat::Text => report::diff(diff, out),
+            OutputFormat::Json => diff.data.save(out),
+        }
+    }
+
+    fn write_report<W: Write>(&self, report: &Report, data: &FnData, out: &mut W)
+                              -> io::Result<()> {
+        match *report {
+            Report::DeadCode(ref roots) => report::dead_code(data, roots, out),
+            Report::Recursion => report::recursion(data, out),
+            Report::Paths { ref from, ref to, search, potential } => {
+                report::paths(data, from, to, search, potential, out)
+            }
+            Report::Unsafe(potential) => report::unsafe_reach(data, potential, out),
+            Report::Panics(potential) => report::panic_reach(data, potential, out),
+            Report::NoAlloc { ref patterns, potential } => {
+                report::alloc_reach(data, patterns, potential, out)
+            }
+        }
+    }
+
+    fn write<W: Write>(&self, data: &FnData, out: &mut W) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Dot => data.dot(&self.dot_options, out),
+            OutputFormat::Text => data.dump(out),
+            OutputFormat::Json => data.save(out),
+        }
+    }
+}
+
+// Parse the numeric argument to opt.
+fn parse_number(opt: &str, arg: Option<&String>) -> Result<usize, String> {
+    let arg = arg.ok_or_else(|| format!("missing argument to {}", opt))?;
+    arg.parse().map_err(|_| format!("invalid argument to {}: `{}`", opt, arg))
+}
+
+// Read the patterns in the file at path, one per line. Blank lines and lines
+// starting with `#` are ignored.
+fn read_patterns(path: &str) -> Result<Vec<Pattern>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Pattern::parse)
+        .collect()
+}
+
+// Coordinates the compiler, only needs to know how to output the callgraph.
+struct CallGraphCalls {
+    config: Config,
+    // Set if we could not write the callgraph.
+    failed: bool,
+}
 
 // A bunch of callbacks from the compiler. We don't do much, mostly accept the
 // default implementations.
-impl<'a> CompilerCalls<'a> for CallGraphCalls {
-    fn build_controller(self: Box<Self>, _: &Session, _:&Matches) -> driver::CompileController<'a> {
-        // Mostly, we want to copy what rustc does.
-        let mut control = driver::CompileController::basic();
-        // Keep expanded_crate after expand step
-        control.keep_ast = true;
-        // But we can stop after analysis, we don't need to generate code.
-        control.after_analysis.stop = Compilation::Stop;
-        control.after_analysis.callback = Box::new(move |state| {
-            // eprintln!("after_analysis");
-            // eprintln!("  krate: {}", if let Some(_) = state.krate {"OK"} else {"FAIL"});
-            // eprintln!("  expanded_crate: {}", state.expanded_crate.map_or_else(|| "FAIL", |_| "OK"));
-            // eprintln!("  HIR crate: {}", state.hir_crate.map_or_else(|| "FAIL", |_| "OK"));
-            // eprintln!("  tcx: {}", state.tcx.map_or_else(|| "FAIL", |_| "OK"));
-            save::process_crate(
-                state.tcx.expect("missing tcx"),
-                state.expanded_crate.expect("missing crate"),
-                state.crate_name.expect("missing crate name"),
-                state.input,
-                None,
-                FnSaveHandler
-            );
+impl rustc_driver::Callbacks for CallGraphCalls {
+    fn config(&mut self, config: &mut interface::Config) {
+        // Don't let the MIR inliner hide calls from us.
+        config.opts.unstable_opts.inline_mir = Some(false);
+        // Let crates mark functions for us, with
+        // `#[cfg_attr(callgraph, callgraph::no_alloc)]`.
+        config.crate_cfg.push("callgraph".to_owned());
+        config.opts.unstable_opts.crate_attr.push("feature(register_tool)".to_owned());
+        config.opts.unstable_opts.crate_attr.push("register_tool(callgraph)".to_owned());
+    }
+
+    fn after_crate_root_parsing<'tcx>(
+        &mut self,
+        compiler: &interface::Compiler,
+        _queries: &'tcx Queries<'tcx>,
+    ) -> Compilation {
+        // This is before the dep-info is written.
+        let mut env_depinfo = compiler.sess.psess.env_depinfo.lock();
+        for var in self.config.env_deps.iter() {
+            let value = env::var(var).ok().map(|v| Symbol::intern(&v));
+            env_depinfo.insert((Symbol::intern(var), value));
+        }
+        Compilation::Continue
+    }
+
+    fn after_analysis<'tcx>(
+        &mut self,
+        _compiler: &interface::Compiler,
+        queries: &'tcx Queries<'tcx>,
+    ) -> Compilation {
+        queries.global_ctxt().unwrap().enter(|tcx| {
+            let crate_name = tcx.crate_name(LOCAL_CRATE);
+            let data = if self.config.mono {
+                mono::collect(tcx, crate_name.as_str())
+            } else {
+                let mut visitor = visitor::FnVisitor::new(tcx);
+                // This actually does the walking, first to find all the
+                // functions, then through their MIR to find the calls between
+                // them.
+                tcx.hir().visit_all_item_likes_in_crate(&mut visitor);
+                mir::collect_calls(tcx, &mut visitor);
+                // When we're done, process the info we collected.
+                visitor.post_process(crate_name.as_str())
+            };
+            // Then produce output.
+            let name = format!("{}-{}", crate_name, target_kind(tcx));
+            if let Err(e) = self.config.emit(data, &name) {
+                eprintln!("error writing callgraph for {}: {}", crate_name, e);
+                self.failed = true;
+            }
         });
 
-        control
+        // We can stop after analysis, we don't need to generate code.
+        if self.config.stop_after_analysis {
+            Compilation::Stop
+        } else {
+            Compilation::Continue
+        }
     }
 }
 
-struct FnSaveHandler;
+// The kind of target being compiled, which tells apart the graphs of a
+// package's targets, which may have the same crate name.
+fn target_kind(tcx: TyCtxt<'_>) -> &'static str {
+    if tcx.sess.opts.test {
+        return "test";
+    }
+    match tcx.crate_types().first() {
+        Some(CrateType::Executable) => "bin",
+        Some(CrateType::ProcMacro) => "proc-macro",
+        _ => "lib",
+    }
+}
 
-impl SaveHandler for FnSaveHandler {
-    fn save<'l, 'tcx>(
-        &mut self,
-        save_ctxt: SaveContext<'l, 'tcx>,
-        krate: &ast::Crate,
-        crate_name: &str,
-        _input: &'l Input
-    )
-    {
-        // eprintln!("SaveHandler");
-        // eprintln!("Krate: {:#?}", krate);
-        let mut visitor = visitor::FnVisitor::new(save_ctxt);
-        // This actually does the walking.
-        visit::walk_crate(&mut visitor, krate);
-        // // When we're done, process the info we collected.
-        let data = visitor.post_process(crate_name);
-        // // Then produce output.
-        data.dump();
-        data.dot();
-    }
-}
-
-// args are the arguments passed on the command line, generally passed through
-// to the compiler.
-pub fn run(args: Vec<String>) {
+// config controls the output, args are the arguments passed through to the
+// compiler (args[0] is the name of the program). Returns the exit code.
+pub fn run(config: Config, args: Vec<String>) -> i32 {
     let mut args = args.clone();
 
     // Create a data structure to control compilation.
-    let calls = Box::new(CallGraphCalls);
+    let mut calls = CallGraphCalls { config, failed: false };
 
-    let sysroot = current_sysroot()
-        .expect("need to specify SYSROOT env var or use rustup or multirust");
+    if !args.iter().any(|a| a == "--sysroot") {
+        let sysroot = current_sysroot()
+            .expect("could not find the sysroot, set the SYSROOT env var");
 
-    args.push("--sysroot".to_owned());
-    args.push(sysroot);
+        args.push("--sysroot".to_owned());
+        args.push(sysroot);
+    }
 
     // Run the compiler!
-    syntax::with_globals(|| {
-        rustc_driver::run_compiler(&args, calls, None, None);
+    let code = rustc_driver::catch_with_exit_code(|| {
+        rustc_driver::RunCompiler::new(&args, &mut calls).run()
     });
+    if code == 0 && calls.failed {
+        1
+    } else {
+        code
+    }
 }
 
-fn current_sysroot() -> Option<String> {
-    let home = env::var("RUSTUP_HOME").or_else(|_| env::var("MULTIRUST_HOME"));
-    let toolchain = env::var("RUSTUP_TOOLCHAIN").or_else(|_| env::var("MULTIRUST_TOOLCHAIN"));
-    if let (Ok(home), Ok(toolchain)) = (home, toolchain) {
-        Some(format!("{}/toolchains/{}", home, toolchain))
+// Output the graphs saved in config.load (merged if there are several),
+// rather than running the compiler. Returns the exit code.
+pub fn run_saved(config: Config) -> i32 {
+    let mut graphs = vec![];
+    for path in config.load.iter() {
+        match FnData::load(path) {
+            Ok(data) => graphs.push(data),
+            Err(e) => {
+                eprintln!("error reading {}: {}", path.display(), e);
+                return 1;
+            }
+        }
+    }
+
+    let data = if graphs.len() == 1 {
+        graphs.pop().unwrap()
     } else {
-        let rustc_exe = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
-        env::var("SYSROOT").ok().or_else(|| {
-            Command::new(rustc_exe)
-                .arg("--print")
-                .arg("sysroot")
-                .output()
-                .ok()
-                .and_then(|out| String::from_utf8(out.stdout).ok())
-                .map(|s| s.trim().to_owned())
-        })
+        FnData::merge(graphs, "merged")
+    };
+    let name = data.crate_name.clone();
+    match config.emit(data, &name) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error writing callgraph: {}", e);
+            1
+        }
+    }
+}
+
+// Compare the graphs saved in config.diff, rather than running the compiler.
+// Returns the exit code.
+pub fn run_diff(config: Config) -> i32 {
+    let (old, new) = config.diff.clone().expect("no graphs to diff");
+    let mut graphs = vec![];
+    for path in [old, new] {
+        match FnData::load(&path) {
+            Ok(data) => graphs.push(data),
+            Err(e) => {
+                eprintln!("error reading {}: {}", path.display(), e);
+                return 1;
+            }
+        }
+    }
+
+    let new = graphs.pop().unwrap();
+    let old = graphs.pop().unwrap();
+    match config.emit_diff(&FnData::diff(old, new)) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error writing diff: {}", e);
+            1
+        }
+    }
+}
+
+// The toolchain we are built with, pinned in rust-toolchain.toml. The crates we
+// analyse, and their dependencies, must be compiled by its rustc.
+pub fn toolchain() -> &'static str {
+    include_str!("../rust-toolchain.toml").lines()
+        .find_map(|line| line.strip_prefix("channel = "))
+        .map(|channel| channel.trim_matches('"'))
+        .expect("no channel in rust-toolchain.toml")
+}
+
+// SYSROOT if it is set, otherwise the sysroot of the toolchain we are running
+// with (where librustc_driver was loaded from), rather than whichever one rustup
+// would pick for the current directory.
+fn current_sysroot() -> Option<String> {
+    env::var("SYSROOT").ok().or_else(|| {
+        filesearch::get_or_default_sysroot().ok().map(|path| path.display().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(opts: &[&str]) -> Result<Config, String> {
+        Config::parse(&opts.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+    }
+
+    fn error(opts: &[&str]) -> String {
+        match parse(opts) {
+            Ok(_) => panic!("no error parsing {:?}", opts),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn parse_options() {
+        let config = parse(&["path", "main", "foo", "--all", "5", "--definite-only"]).unwrap();
+        assert!(matches!(config.report,
+                         Some(Report::Paths { search: PathSearch::All(5), potential: false, .. })));
+        let config = parse(&["callers", "foo", "--depth", "2", "-o", "-"]).unwrap();
+        assert_eq!(config.query.unwrap().max_depth, Some(2));
+        assert_eq!(config.output, Some(PathBuf::from("-")));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(error(&["--frobnicate"]), "unknown option `--frobnicate`");
+        assert_eq!(error(&["callers"]), "missing pattern for callers");
+        assert_eq!(error(&["path", "main"]), "missing TO pattern for path");
+        assert_eq!(error(&["diff", "old.json"]), "missing NEW graph for diff");
+        assert_eq!(error(&["--output"]), "missing argument to --output");
+        assert_eq!(error(&["--format", "svg"]), "unknown format `svg`");
+        assert_eq!(error(&["--aggregate", "crate"]), "unknown aggregation level `crate`");
+        assert_eq!(error(&["path", "a", "b", "--shortest", "x"]),
+                   "invalid argument to --shortest: `x`");
+        assert_eq!(error(&["--depth", "1"]), "`--depth` can only be used with callers or callees");
+        assert_eq!(error(&["dead-code", "--shortest", "1"]), "`--shortest` can only be used with path");
+        assert_eq!(error(&["recursion", "--definite-only"]),
+                   "`--definite-only` can only be used with callers, callees, path, unsafe, \
+                    panics, or no-alloc");
+        assert_eq!(error(&["unsafe", "--roots", "main"]), "`--roots` can only be used with dead-code");
+        assert_eq!(error(&["panics", "--list", "fns.txt"]), "`--list` can only be used with no-alloc");
+        assert!(error(&["no-alloc", "--list", "/nonexistent/fns.txt"]).starts_with("could not read"));
     }
 }
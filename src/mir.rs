@@ -0,0 +1,242 @@
+use rustc_hir::def_id::DefId;
+use rustc_hir::LangItem;
+use rustc_middle::mir::visit::Visitor;
+use rustc_middle::mir::{AssertKind, Body, CallSource, CastKind, Location, Operand, Place, Rvalue,
+                        Terminator, TerminatorKind, RETURN_PLACE};
+use rustc_middle::ty::adjustment::PointerCoercion;
+use rustc_middle::ty::{self, GenericArgsRef, Instance, InstanceDef, Ty, TyCtxt};
+use rustc_span::{sym, Span};
+
+use crate::fndata::{CallKind, CallSyntax, Id};
+use crate::visitor::{id_from_def_id, FnVisitor};
+
+// Collects calls from the MIR of every local function, closure, const, and
+// static, and records them in fns.
+//
+// MIR makes explicit the calls which are hidden in the source: overloaded
+// operators, Deref, Drop, `?` (From::from), for loops (IntoIterator::into_iter
+// and Iterator::next), bounds and overflow checks (which call a panic function
+// if they fail), and so on. Calls from a body which is not a node (e.g., the
+// body of an async fn, or a closure from a macro) are attributed to the
+// function which contains it.
+pub fn collect_calls<'tcx>(tcx: TyCtxt<'tcx>, fns: &mut FnVisitor<'tcx>) {
+    for &def_id in tcx.mir_keys(()).iter() {
+        let def_id = def_id.to_def_id();
+        let mut caller = id_from_def_id(def_id);
+        if !fns.is_function(caller) {
+            caller = id_from_def_id(tcx.typeck_root_def_id(def_id));
+        }
+        // E.g., generated code which FnVisitor skipped.
+        if !fns.is_function(caller) {
+            continue;
+        }
+
+        // Consts and statics only have MIR for compile time evaluation.
+        let body = tcx.instance_mir(InstanceDef::Item(def_id));
+        let mut collector = CallCollector { tcx, body, caller, fns: &mut *fns };
+        collector.visit_body(body);
+    }
+}
+
+struct CallCollector<'a, 'tcx> {
+    tcx: TyCtxt<'tcx>,
+    body: &'a Body<'tcx>,
+    // The function whose body we are visiting.
+    caller: Id,
+    fns: &'a mut FnVisitor<'tcx>,
+}
+
+impl<'a, 'tcx> CallCollector<'a, 'tcx> {
+    // Work out what a call to def_id with args will really call. For a trait
+    // method where the receiver type is known, that is the single impl (or
+    // default method) which will be called. If the receiver is a trait object
+    // or a generic parameter we can't know, so return the trait method's decl
+    // as a Potential call.
+    fn resolve(&self, def_id: DefId, args: GenericArgsRef<'tcx>) -> (DefId, CallKind) {
+        if self.tcx.trait_of_item(def_id).is_none() {
+            return (def_id, CallKind::Definite);
+        }
+
+        let param_env = self.tcx.param_env_reveal_all_normalized(self.body.source.def_id());
+        match Instance::resolve(self.tcx, param_env, def_id, args) {
+            Ok(Some(instance)) => match instance.def {
+                InstanceDef::Item(callee) => (callee, CallKind::Definite),
+                // Dynamic dispatch through a vtable.
+                InstanceDef::Virtual(..) => (def_id, CallKind::Potential),
+                // Some kind of shim (e.g., for closures, fn pointers, or
+                // clone), treat it as a call to the trait method.
+                _ => (def_id, CallKind::Definite),
+            },
+            // Still generic, we'll only know after monomorphisation.
+            Ok(None) | Err(_) => (def_id, CallKind::Potential),
+        }
+    }
+
+    // `collect` and `into` are generic functions of core which just call
+    // FromIterator::from_iter and From::from for their type arguments. We don't
+    // see the calls made inside other crates, so we record that one as if it
+    // were made here, e.g., so that collecting into a Vec allocates.
+    fn forwarded_call(&self,
+                      def_id: DefId,
+                      args: GenericArgsRef<'tcx>)
+                      -> Option<(DefId, GenericArgsRef<'tcx>)> {
+        let tcx = self.tcx;
+        if tcx.is_diagnostic_item(sym::iterator_collect_fn, def_id) {
+            // `iter.collect::<B>()` is `B::from_iter(iter)`.
+            let (iter, b) = (args.type_at(0), args.type_at(1));
+            let from_iter = tcx.get_diagnostic_item(sym::FromIterator)
+                .and_then(|t| tcx.associated_item_def_ids(t).first().copied())?;
+            let item = Ty::new_projection(tcx, tcx.get_diagnostic_item(sym::IteratorItem)?, [iter]);
+            let param_env = tcx.param_env_reveal_all_normalized(self.body.source.def_id());
+            let item = tcx.try_normalize_erasing_regions(param_env, item).ok()?;
+            Some((from_iter, tcx.mk_args(&[b.into(), item.into(), iter.into()])))
+        } else if tcx.trait_of_item(def_id).map_or(false, |t| tcx.is_diagnostic_item(sym::Into, t)) {
+            // `t.into()` is `U::from(t)`, unless there's an impl of Into itself.
+            let from = tcx.get_diagnostic_item(sym::from_fn)?;
+            Some((from, tcx.mk_args(&[args.type_at(1).into(), args.type_at(0).into()])))
+        } else {
+            None
+        }
+    }
+
+    // Record op as address taken if it is a fn item.
+    fn record_fn_use(&mut self, op: &Operand<'tcx>, span: Span) {
+        let ty = op.ty(self.body, self.tcx);
+        if let ty::FnDef(def_id, args) = *ty.kind() {
+            let (def_id, _) = self.resolve(def_id, args);
+            self.fns.record_address_taken(self.caller, def_id, ty.fn_sig(self.tcx), span);
+        }
+    }
+}
+
+// See https://doc.rust-lang.org/nightly/nightly-rustc/rustc_middle/mir/visit/trait.Visitor.html
+impl<'a, 'tcx> Visitor<'tcx> for CallCollector<'a, 'tcx> {
+    fn visit_terminator(&mut self, terminator: &Terminator<'tcx>, location: Location) {
+        let span = terminator.source_info.span;
+        match terminator.kind {
+            // Calls to fn items have a statically known callee, whether the
+            // fn item is a constant or a local holding one (calls to closures
+            // are calls to a method of Fn, which resolves to the closure). A
+            // call through a fn pointer might go to any function whose address
+            // is taken, see FnVisitor::record_fn_ptr_call.
+            TerminatorKind::Call { ref func, ref args, .. } => {
+                let syntax = call_syntax(terminator);
+                match *func.ty(self.body, self.tcx).kind() {
+                    ty::FnDef(def_id, args) => {
+                        let (callee, kind) = self.resolve(def_id, args);
+                        self.fns.record_call(self.caller, callee, kind, syntax, span);
+                        if let Some((def_id, args)) = self.forwarded_call(def_id, args) {
+                            if let (callee, CallKind::Definite) = self.resolve(def_id, args) {
+                                self.fns.record_call(self.caller,
+                                                     callee,
+                                                     CallKind::Definite,
+                                                     CallSyntax::Implicit,
+                                                     span);
+                            }
+                        }
+                    }
+                    ty::FnPtr(sig) => self.fns.record_fn_ptr_call(self.caller, sig, syntax, span),
+                    _ => {}
+                }
+                // A fn item passed to the callee may be called from there.
+                for arg in args.iter() {
+                    let site = if arg.span.is_dummy() { span } else { arg.span };
+                    self.record_fn_use(&arg.node, site);
+                }
+            }
+            // A bounds or overflow check (etc.) calls a panic function if it
+            // fails. Alignment checks are only there with debug assertions, so
+            // we leave them out.
+            TerminatorKind::Assert { ref msg, .. }
+                if !matches!(**msg, AssertKind::MisalignedPointerDereference { .. }) =>
+            {
+                let item = match **msg {
+                    AssertKind::BoundsCheck { .. } => LangItem::PanicBoundsCheck,
+                    _ => msg.panic_function(),
+                };
+                let panic = self.tcx.require_lang_item(item, Some(span));
+                self.fns.record_call(self.caller, panic, CallKind::Definite, CallSyntax::Implicit, span);
+            }
+            // Dropping a value of a type with a Drop impl calls its drop
+            // method. We don't follow the drop glue into fields.
+            TerminatorKind::Drop { ref place, .. } => {
+                let ty = place.ty(self.body, self.tcx).ty;
+                if let Some(dtor) = ty.ty_adt_def().and_then(|adt| adt.destructor(self.tcx)) {
+                    self.fns.record_call(self.caller,
+                                         dtor.did,
+                                         CallKind::Definite,
+                                         CallSyntax::Implicit,
+                                         span);
+                }
+            }
+            _ => {}
+        }
+
+        self.super_terminator(terminator, location);
+    }
+
+    // A fn item which escapes, e.g., is cast to a fn pointer or stored in a
+    // struct.
+    fn visit_assign(&mut self, place: &Place<'tcx>, rvalue: &Rvalue<'tcx>, location: Location) {
+        let span = self.body.source_info(location).span;
+        for op in escaping_operands(place, rvalue) {
+            self.record_fn_use(op, span);
+        }
+        self.super_assign(place, rvalue, location);
+    }
+
+    // A closure which is cast to a fn pointer.
+    fn visit_rvalue(&mut self, rvalue: &Rvalue<'tcx>, location: Location) {
+        if let Rvalue::Cast(CastKind::PointerCoercion(PointerCoercion::ClosureFnPointer(_)),
+                            ref op,
+                            target) = *rvalue {
+            if let ty::Closure(def_id, _) = *op.ty(self.body, self.tcx).kind() {
+                let span = self.body.source_info(location).span;
+                self.fns.record_address_taken(self.caller, def_id, target.fn_sig(self.tcx), span);
+            }
+        }
+        self.super_rvalue(rvalue, location);
+    }
+}
+
+// The operands of an assignment through which a fn item or closure escapes and
+// may be called later: those which are cast to a fn pointer, put in a struct,
+// tuple, array, or closure, stored through a projection, or returned. Assigning
+// one to a local doesn't count, the uses of the local do. Call arguments escape
+// too, but aren't part of an assignment.
+pub fn escaping_operands<'a, 'tcx>(place: &Place<'tcx>,
+                                   rvalue: &'a Rvalue<'tcx>)
+                                   -> Vec<&'a Operand<'tcx>> {
+    match *rvalue {
+        Rvalue::Cast(CastKind::PointerCoercion(PointerCoercion::ReifyFnPointer
+                                               | PointerCoercion::ClosureFnPointer(_)),
+                     ref op,
+                     _) => vec![op],
+        Rvalue::Aggregate(_, ref ops) => ops.iter().collect(),
+        Rvalue::Repeat(ref op, _) => vec![op],
+        Rvalue::Use(ref op) if place.local == RETURN_PLACE || !place.projection.is_empty() => {
+            vec![op]
+        }
+        _ => vec![],
+    }
+}
+
+// How the Call terminator was written, see CallSyntax. Calls the compiler
+// inserts are not Normal calls or come from desugaring. For a method call, the
+// terminator's span covers the receiver, but its fn_span starts at the method's
+// name.
+pub fn call_syntax(terminator: &Terminator<'_>) -> CallSyntax {
+    let span = terminator.source_info.span;
+    match terminator.kind {
+        TerminatorKind::Call { call_source: CallSource::Normal, fn_span, .. } => {
+            if span.desugaring_kind().is_some() {
+                CallSyntax::Implicit
+            } else if fn_span != span {
+                CallSyntax::Method
+            } else {
+                CallSyntax::Direct
+            }
+        }
+        _ => CallSyntax::Implicit,
+    }
+}
@@ -0,0 +1,443 @@
+use rustc_hir::def::DefKind;
+use rustc_hir::def_id::{DefId, LOCAL_CRATE};
+use rustc_hir::{CoroutineKind, CoroutineSource, LangItem};
+use rustc_middle::mir::visit::Visitor;
+use rustc_middle::mir::interpret::{GlobalAlloc, Scalar};
+use rustc_middle::mir::{AggregateKind, Body, CastKind, ConstOperand, ConstValue, Location, Operand,
+                        Place, Rvalue, Terminator, TerminatorKind};
+use rustc_middle::traits::ImplSource;
+use rustc_middle::ty::adjustment::{CustomCoerceUnsized, PointerCoercion};
+use rustc_middle::ty::{self, EarlyBinder, GenericArgKind, GenericArgsRef, Instance, InstanceDef,
+                       ParamEnv, Ty, TyCtxt, TypeFoldable};
+use rustc_span::{Span, DUMMY_SP};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::fndata::{CallKind, CallSyntax, Calls, FnData, FnNode, FnPath, Id};
+use crate::mir::{call_syntax, escaping_operands};
+use crate::visitor::{fn_node, fn_path, source_loc, UnsafeBlocks};
+
+// Builds a callgraph of monomorphised functions, rather than of functions as
+// written. Like rustc's mono item collector, we start at the roots of the crate
+// and follow calls from each instance, so a generic function gets one node per
+// instantiation which is reachable from the roots.
+//
+// Only local functions are nodes. Calls through trait objects are Potential
+// calls to every method of a vtable, from wherever the vtable is created. Fn
+// items which escape (see mir::escaping_operands) have their address taken,
+// closures and async blocks are contained in the instance which creates them,
+// and the body of an async fn is part of the function. Calls through fn
+// pointers are not matched to the functions whose address is taken.
+//
+// An upstream generic function (or shim) instantiated with a local type,
+// closure, or fn item may call back into local code, e.g., `map(bar)` calls
+// `bar` from `Map::next`. We walk the bodies of those on behalf of the local
+// caller, and record the local functions they reach as implicit calls from the
+// local call site.
+pub fn collect(tcx: TyCtxt<'_>, crate_name: &str) -> FnData {
+    let mut collector = MonoCollector {
+        tcx,
+        ids: HashMap::new(),
+        queue: VecDeque::new(),
+        walked: HashSet::new(),
+        static_calls: Calls::new(),
+        dynamic_calls: Calls::new(),
+        contains: Calls::new(),
+        address_taken: Calls::new(),
+        functions: HashMap::new(),
+        unsafe_blocks: UnsafeBlocks::collect(tcx),
+    };
+
+    for root in roots(tcx) {
+        collector.node(root);
+    }
+    while let Some(instance) = collector.queue.pop_front() {
+        let caller = collector.ids[&instance];
+        let body = tcx.instance_mir(instance.def);
+        let mut visitor = InstanceVisitor { collector: &mut collector, instance, caller, body, site: None };
+        visitor.visit_body(body);
+    }
+
+    FnData {
+        static_calls: collector.static_calls,
+        dynamic_calls: collector.dynamic_calls,
+        contains: collector.contains,
+        address_taken: collector.address_taken,
+        functions: collector.functions,
+        crate_name: crate_name.to_string(),
+    }
+}
+
+// The functions we start from: main, exported functions (pub functions of a
+// library, `#[no_mangle]` and other extern functions). Generic functions can't
+// be roots, they are only reached through their callers.
+fn roots(tcx: TyCtxt<'_>) -> Vec<Instance<'_>> {
+    let mut roots = vec![];
+    if let Some((def_id, _)) = tcx.entry_fn(()) {
+        roots.push(def_id);
+    }
+
+    for def_id in tcx.hir().body_owners() {
+        if !matches!(tcx.def_kind(def_id), DefKind::Fn | DefKind::AssocFn) {
+            continue;
+        }
+        if tcx.effective_visibilities(()).is_exported(def_id)
+            || tcx.codegen_fn_attrs(def_id).contains_extern_indicator()
+        {
+            roots.push(def_id.to_def_id());
+        }
+    }
+
+    roots.into_iter()
+        .filter(|&def_id| !tcx.generics_of(def_id).requires_monomorphization(tcx))
+        .map(|def_id| Instance::mono(tcx, def_id))
+        .collect()
+}
+
+struct MonoCollector<'tcx> {
+    tcx: TyCtxt<'tcx>,
+    // Every instance we have found, and its node in the graph. Ids are just
+    // numbered in the order we find instances, they don't correspond to DefIds.
+    ids: HashMap<Instance<'tcx>, Id>,
+    // Instances whose bodies we have yet to visit.
+    queue: VecDeque<Instance<'tcx>>,
+    // The (caller, instance) pairs where we have walked the body of an
+    // upstream instance (or async fn body) on behalf of the caller.
+    walked: HashSet<(Id, Instance<'tcx>)>,
+
+    static_calls: Calls,
+    dynamic_calls: Calls,
+    contains: Calls,
+    address_taken: Calls,
+    functions: HashMap<Id, FnNode>,
+    unsafe_blocks: UnsafeBlocks,
+}
+
+impl<'tcx> MonoCollector<'tcx> {
+    // The node for instance, if it is in the graph. If we haven't seen the
+    // instance before, it is queued to be visited.
+    fn node(&mut self, instance: Instance<'tcx>) -> Option<Id> {
+        // Shims (drop glue, vtable shims, etc.) and constructors are not nodes.
+        if !instance.def_id().is_local()
+            || !matches!(instance.def, InstanceDef::Item(_))
+            || matches!(self.tcx.def_kind(instance.def_id()), DefKind::Ctor(..))
+        {
+            return None;
+        }
+        if let Some(&id) = self.ids.get(&instance) {
+            return Some(id);
+        }
+
+        let id = Id {
+            krate: LOCAL_CRATE.as_u32(),
+            index: self.ids.len() as u32,
+        };
+        let name = self.tcx.def_path_str_with_args(instance.def_id(), instance.args);
+        self.ids.insert(instance, id);
+        let mut node = fn_node(self.tcx, instance.def_id(), name);
+        node.path = self.instance_path(instance);
+        self.functions.insert(id, node);
+        self.queue.push_back(instance);
+        Some(id)
+    }
+
+    // Instances of the same function are distinguished by their generic
+    // arguments.
+    fn instance_path(&self, instance: Instance<'tcx>) -> FnPath {
+        let mut path = fn_path(self.tcx, instance.def_id());
+        if !instance.args.is_empty() {
+            path.def_path = format!("{}::<{:?}>", path.def_path, instance.args);
+        }
+        path
+    }
+
+    fn record_call(&mut self,
+                   from: Id,
+                   to: Instance<'tcx>,
+                   kind: CallKind,
+                   syntax: CallSyntax,
+                   span: Span) {
+        if let Some(to) = self.node(to) {
+            let site = source_loc(self.tcx, span);
+            let in_unsafe = self.unsafe_blocks.contains(span);
+            let calls = match kind {
+                CallKind::Definite => &mut self.static_calls,
+                CallKind::Potential => &mut self.dynamic_calls,
+                CallKind::Contains => &mut self.contains,
+                CallKind::AddressTaken => &mut self.address_taken,
+            };
+            let sites = calls.entry((from, to)).or_default();
+            match kind {
+                CallKind::Definite | CallKind::Potential => sites.add_call(site, syntax, in_unsafe),
+                CallKind::Contains | CallKind::AddressTaken => sites.add(site, false),
+            }
+        }
+    }
+
+    // Whether we should walk the body of instance, which is not a node, for
+    // calls back into local code: it is a shim, or an upstream function
+    // instantiated with a local type, closure, or fn item.
+    fn calls_back(&self, instance: Instance<'tcx>) -> bool {
+        let tcx = self.tcx;
+        match instance.def {
+            InstanceDef::Item(def_id)
+                if def_id.is_local()
+                    || matches!(tcx.def_kind(def_id), DefKind::Ctor(..))
+                    || !tcx.is_mir_available(def_id) => false,
+            // These have no body.
+            InstanceDef::Intrinsic(_) | InstanceDef::Virtual(..) => false,
+            // The args are the closure's, which don't include the closure.
+            InstanceDef::ClosureOnceShim { .. } => true,
+            _ => instance.args.iter().flat_map(|arg| arg.walk()).any(|arg| {
+                matches!(arg.unpack(), GenericArgKind::Type(ty) if is_local_type(ty))
+            }),
+        }
+    }
+}
+
+// Whether ty is defined in the local crate (not whether it contains a local
+// type).
+fn is_local_type(ty: Ty<'_>) -> bool {
+    match *ty.kind() {
+        ty::Adt(adt, _) => adt.did().is_local(),
+        ty::Foreign(def_id)
+        | ty::FnDef(def_id, _)
+        | ty::Closure(def_id, _)
+        | ty::CoroutineClosure(def_id, _)
+        | ty::Coroutine(def_id, _) => def_id.is_local(),
+        ty::Dynamic(preds, ..) => preds.principal_def_id().map_or(false, |def_id| def_id.is_local()),
+        _ => false,
+    }
+}
+
+// Visits the MIR of a single instance.
+struct InstanceVisitor<'a, 'tcx> {
+    collector: &'a mut MonoCollector<'tcx>,
+    instance: Instance<'tcx>,
+    caller: Id,
+    body: &'tcx Body<'tcx>,
+    // When walking an upstream body on behalf of caller, the local site to
+    // record instead of the spans in that body, and the kind of edge through
+    // which it was reached.
+    site: Option<(Span, CallKind)>,
+}
+
+impl<'a, 'tcx> InstanceVisitor<'a, 'tcx> {
+    // Substitute the instance's generic arguments into value, which comes from
+    // its (generic) MIR.
+    fn monomorphize<T: TypeFoldable<TyCtxt<'tcx>>>(&self, value: T) -> T {
+        self.instance.instantiate_mir_and_normalize_erasing_regions(
+            self.collector.tcx,
+            ParamEnv::reveal_all(),
+            EarlyBinder::bind(value),
+        )
+    }
+
+    // Record an edge from the caller to instance if it is a node, or else walk
+    // its body for the local functions it reaches.
+    fn reach(&mut self, instance: Instance<'tcx>, kind: CallKind, syntax: CallSyntax, span: Span) {
+        let (kind, syntax, span) = match self.site {
+            // A function called from something which might be called is itself
+            // only a potential call.
+            Some((site, via @ (CallKind::Potential | CallKind::AddressTaken)))
+                if kind == CallKind::Definite => (via, CallSyntax::Implicit, site),
+            Some((site, _)) => (kind, CallSyntax::Implicit, site),
+            None => (kind, syntax, span),
+        };
+        if self.collector.node(instance).is_some() {
+            self.collector.record_call(self.caller, instance, kind, syntax, span);
+        } else if self.collector.calls_back(instance) {
+            self.walk(instance, Some((span, kind)));
+        }
+    }
+
+    // Visit the body of instance as if it were part of the caller's.
+    fn walk(&mut self, instance: Instance<'tcx>, site: Option<(Span, CallKind)>) {
+        if !self.collector.walked.insert((self.caller, instance)) {
+            return;
+        }
+        let body = self.collector.tcx.instance_mir(instance.def);
+        let mut visitor = InstanceVisitor {
+            collector: &mut *self.collector,
+            instance,
+            caller: self.caller,
+            body,
+            site,
+        };
+        visitor.visit_body(body);
+    }
+
+    // A fn item which escapes, see mir::escaping_operands. A closure which
+    // captures nothing is a constant rather than an aggregate in optimized MIR,
+    // so this is also where it is created.
+    fn visit_fn_use(&mut self, op: &Operand<'tcx>, span: Span) {
+        let tcx = self.collector.tcx;
+        match *self.monomorphize(op.ty(self.body, tcx)).kind() {
+            ty::FnDef(def_id, args) => {
+                if let Some(instance) = Instance::resolve_for_fn_ptr(tcx, ParamEnv::reveal_all(), def_id, args) {
+                    self.reach(instance, CallKind::AddressTaken, CallSyntax::Implicit, span);
+                }
+            }
+            ty::Closure(def_id, args) | ty::Coroutine(def_id, args) if op.constant().is_some() => {
+                self.create_closure(def_id, args, span);
+            }
+            _ => {}
+        }
+    }
+
+    // Creating a closure or async block contains it, except for the body of an
+    // async fn, which is part of the function.
+    fn create_closure(&mut self, def_id: DefId, args: GenericArgsRef<'tcx>, span: Span) {
+        let tcx = self.collector.tcx;
+        if tcx.parent(def_id) != self.instance.def_id() {
+            return;
+        }
+        let instance = Instance::new(def_id, args);
+        if matches!(tcx.coroutine_kind(def_id), Some(CoroutineKind::Desugared(_, CoroutineSource::Fn))) {
+            self.walk(instance, self.site);
+        } else {
+            self.reach(instance, CallKind::Contains, CallSyntax::Implicit, span);
+        }
+    }
+}
+
+// See https://doc.rust-lang.org/nightly/nightly-rustc/rustc_middle/mir/visit/trait.Visitor.html
+impl<'a, 'tcx> Visitor<'tcx> for InstanceVisitor<'a, 'tcx> {
+    fn visit_terminator(&mut self, terminator: &Terminator<'tcx>, location: Location) {
+        let tcx = self.collector.tcx;
+        let span = terminator.source_info.span;
+        match terminator.kind {
+            TerminatorKind::Call { ref func, ref args, .. } => {
+                let callee_ty = self.monomorphize(func.ty(self.body, tcx));
+                if let ty::FnDef(def_id, args) = *callee_ty.kind() {
+                    // Virtual calls are handled where the vtable is created.
+                    if let Ok(Some(callee)) = Instance::resolve(tcx, ParamEnv::reveal_all(), def_id, args) {
+                        let syntax = call_syntax(terminator);
+                        self.reach(callee, CallKind::Definite, syntax, span);
+                    }
+                }
+                for arg in args.iter() {
+                    let site = if arg.span.is_dummy() { span } else { arg.span };
+                    self.visit_fn_use(&arg.node, site);
+                }
+            }
+            // The drop glue calls the Drop impls of the value and its fields.
+            TerminatorKind::Drop { ref place, .. } => {
+                let ty = self.monomorphize(place.ty(self.body, tcx).ty);
+                let glue = Instance::resolve_drop_in_place(tcx, ty);
+                self.reach(glue, CallKind::Definite, CallSyntax::Implicit, span);
+            }
+            _ => {}
+        }
+
+        self.super_terminator(terminator, location);
+    }
+
+    // A fn pointer which is a constant, e.g., one chosen in a const block.
+    fn visit_constant(&mut self, constant: &ConstOperand<'tcx>, location: Location) {
+        let tcx = self.collector.tcx;
+        let value = self.monomorphize(constant.const_);
+        if value.ty().is_fn_ptr() {
+            if let Ok(ConstValue::Scalar(Scalar::Ptr(ptr, _))) = value.eval(tcx, ParamEnv::reveal_all(), constant.span) {
+                if let GlobalAlloc::Function(instance) = tcx.global_alloc(ptr.provenance.alloc_id()) {
+                    let span = self.body.source_info(location).span;
+                    self.reach(instance, CallKind::AddressTaken, CallSyntax::Implicit, span);
+                }
+            }
+        }
+        self.super_constant(constant, location);
+    }
+
+    fn visit_assign(&mut self, place: &Place<'tcx>, rvalue: &Rvalue<'tcx>, location: Location) {
+        let span = self.body.source_info(location).span;
+        for op in escaping_operands(place, rvalue) {
+            self.visit_fn_use(op, span);
+        }
+        self.super_assign(place, rvalue, location);
+    }
+
+    // An unsizing coercion to a trait object creates a vtable, all of whose
+    // methods might be called. Casting a closure to a fn pointer takes its
+    // address.
+    fn visit_rvalue(&mut self, rvalue: &Rvalue<'tcx>, location: Location) {
+        let tcx = self.collector.tcx;
+        let span = self.body.source_info(location).span;
+        match *rvalue {
+            // Closures which capture nothing are constants instead, see
+            // visit_fn_use.
+            Rvalue::Aggregate(ref aggregate, _) => match **aggregate {
+                AggregateKind::Closure(def_id, args) | AggregateKind::Coroutine(def_id, args) => {
+                    self.create_closure(def_id, self.monomorphize(args), span);
+                }
+                _ => {}
+            },
+            Rvalue::Cast(CastKind::PointerCoercion(PointerCoercion::ClosureFnPointer(_)), ref op, _) => {
+                if let ty::Closure(def_id, args) = *self.monomorphize(op.ty(self.body, tcx)).kind() {
+                    let instance = Instance::new(def_id, args);
+                    self.reach(instance, CallKind::AddressTaken, CallSyntax::Implicit, span);
+                }
+            }
+            Rvalue::Cast(CastKind::PointerCoercion(PointerCoercion::Unsize), ref op, target) => {
+                let source = self.monomorphize(op.ty(self.body, tcx));
+                let target = self.monomorphize(target);
+                if let Some((concrete, principal)) = vtable_types(tcx, source, target) {
+                    let trait_ref = tcx.erase_regions(principal.with_self_ty(tcx, concrete));
+                    for entry in tcx.vtable_entries(trait_ref) {
+                        if let ty::VtblEntry::Method(method) = *entry {
+                            self.reach(method, CallKind::Potential, CallSyntax::Implicit, span);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        self.super_rvalue(rvalue, location);
+    }
+}
+
+// For an unsizing coercion from source to target (e.g., `&Foo` to `&dyn Bar`,
+// or `Rc<Foo>` to `Rc<dyn Bar>`), the concrete type and trait of the vtable it
+// creates, if any.
+fn vtable_types<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    source: Ty<'tcx>,
+    target: Ty<'tcx>,
+) -> Option<(Ty<'tcx>, ty::PolyExistentialTraitRef<'tcx>)> {
+    let (source, target) = unsized_types(tcx, source, target)?;
+    match *target.kind() {
+        ty::Dynamic(preds, ..) if !source.is_trait() => preds.principal().map(|p| (source, p)),
+        _ => None,
+    }
+}
+
+// The types behind the pointers of an unsizing coercion, following struct tails
+// (e.g., `Wrapper<Foo>` to `Wrapper<dyn Bar>` unsizes its last field) and the
+// field which a CoerceUnsized impl unsizes, like rustc's
+// find_vtable_types_for_unsizing.
+fn unsized_types<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    source: Ty<'tcx>,
+    target: Ty<'tcx>,
+) -> Option<(Ty<'tcx>, Ty<'tcx>)> {
+    let param_env = ParamEnv::reveal_all();
+    let tails = |source, target| tcx.struct_lockstep_tails_erasing_lifetimes(source, target, param_env);
+    match (source.kind(), target.kind()) {
+        (&ty::Ref(_, source, _), &ty::Ref(_, target, _) | &ty::RawPtr(target, _))
+        | (&ty::RawPtr(source, _), &ty::RawPtr(target, _)) => Some(tails(source, target)),
+        _ if source.is_box() && target.is_box() => Some(tails(source.boxed_ty(), target.boxed_ty())),
+        (&ty::Adt(adt, source_args), &ty::Adt(_, target_args)) => {
+            let trait_ref =
+                ty::TraitRef::from_lang_item(tcx, LangItem::CoerceUnsized, DUMMY_SP, [source, target]);
+            let impl_def_id = match tcx.codegen_select_candidate((param_env, trait_ref)) {
+                Ok(ImplSource::UserDefined(data)) => data.impl_def_id,
+                _ => return None,
+            };
+            let CustomCoerceUnsized::Struct(index) = tcx.coerce_unsized_info(impl_def_id).ok()?.custom_kind?;
+            let field = &adt.non_enum_variant().fields[index];
+            let field_ty = |args| tcx.normalize_erasing_regions(param_env, field.ty(tcx, args));
+            unsized_types(tcx, field_ty(source_args), field_ty(target_args))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,147 @@
+// Matching function names against patterns given on the command line.
+
+use regex::Regex;
+
+use crate::fndata::FnNode;
+
+use std::fmt;
+
+// A pattern is a glob, where `*` matches any sequence of characters (including
+// `::`), or a regex if it starts with `re:`. A glob without a `*` matches a name
+// exactly, or any name which ends with `::` and the pattern, so `bar` matches
+// `foo::bar` but not `foobar`. Regexes match if they match any part of a name.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Pattern, String> {
+        match pattern.strip_prefix("re:") {
+            Some(re) => Regex::new(re)
+                .map(Pattern::Regex)
+                .map_err(|e| format!("invalid regex `{}`: {}", re, e)),
+            None => Ok(Pattern::Glob(pattern.to_owned())),
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match *self {
+            Pattern::Glob(ref pattern) if pattern.contains('*') => {
+                glob(pattern.as_bytes(), name.as_bytes())
+            }
+            Pattern::Glob(ref pattern) => {
+                name == pattern || name.ends_with(&format!("::{}", pattern))
+            }
+            Pattern::Regex(ref re) => re.is_match(name),
+        }
+    }
+
+    // Names of functions in the crate being compiled don't start with the
+    // crate's name, so we also try the name with the crate's name in front
+    // (e.g., `mycrate::parser::parse`).
+    pub fn matches_node(&self, node: &FnNode) -> bool {
+        self.matches(&node.name) || self.matches(&format!("{}::{}", node.path.krate, node.name))
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Pattern::Glob(ref pattern) => write!(f, "{}", pattern),
+            Pattern::Regex(ref re) => write!(f, "re:{}", re),
+        }
+    }
+}
+
+// Match name against the whole of pattern. When a match fails after a `*`, we
+// backtrack to let the last `*` match one more character.
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // The position after the last `*`, and the position in name it matched up to.
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            p += 1;
+            star = Some((p, n));
+        } else if p < pattern.len() && pattern[p] == name[n] {
+            p += 1;
+            n += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p;
+            n = star_n + 1;
+            star = Some((star_p, n));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fndata::tests::node;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        Pattern::parse(pattern).unwrap().matches(name)
+    }
+
+    #[test]
+    fn glob_without_star() {
+        assert!(matches("bar", "bar"));
+        assert!(matches("bar", "foo::bar"));
+        assert!(matches("Foo::bar", "foo::Foo::bar"));
+        assert!(!matches("bar", "foobar"));
+        assert!(!matches("bar", "bar::baz"));
+        assert!(!matches("foo::bar", "bar"));
+    }
+
+    #[test]
+    fn glob_with_star() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "foo::bar"));
+        assert!(matches("foo::*", "foo::bar::baz"));
+        assert!(matches("*::baz", "foo::bar::baz"));
+        assert!(matches("foo*baz", "foo::bar::baz"));
+        assert!(matches("*bar*", "foo::bar::baz"));
+        // A star has to backtrack past an earlier partial match.
+        assert!(matches("*ab", "aab"));
+        assert!(matches("a*a*a", "aaaa"));
+        assert!(!matches("foo::*", "bar::foo::baz"));
+        assert!(!matches("*::baz", "foo::bazz"));
+        assert!(!matches("a*a*a", "aa"));
+    }
+
+    #[test]
+    fn regex() {
+        assert!(matches("re:^list$", "list"));
+        assert!(!matches("re:^list$", "foo::list"));
+        assert!(matches("re:par(se|ser)", "foo::parser::new"));
+        assert!(matches("re:::new$", "Foo::new"));
+        assert!(!matches("re:::new$", "Foo::new_in"));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        let err = Pattern::parse("re:(").unwrap_err();
+        assert!(err.starts_with("invalid regex `(`"), "{}", err);
+    }
+
+    #[test]
+    fn matches_node_with_crate_name() {
+        let node = node("mycrate", 0, "parser::parse", false);
+        assert!(Pattern::parse("mycrate::parser::*").unwrap().matches_node(&node));
+        assert!(Pattern::parse("parser::parse").unwrap().matches_node(&node));
+        assert!(!Pattern::parse("other::*").unwrap().matches_node(&node));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Pattern::parse("foo::*").unwrap().to_string(), "foo::*");
+        assert_eq!(Pattern::parse("re:^foo").unwrap().to_string(), "re:^foo");
+    }
+}
@@ -0,0 +1,200 @@
+// Text reports about a callgraph, rather than the graph itself.
+
+use crate::fndata::{CallKind, Edge, FnData, GraphDiff, PathSearch, Roots};
+use crate::pattern::Pattern;
+
+use std::io::{self, Write};
+
+// List the functions which can't be reached from roots, one per line, with
+// where they are defined.
+pub fn dead_code<W: Write>(data: &FnData, roots: &Roots, out: &mut W) -> io::Result<()> {
+    let unreached = data.unreachable(roots);
+    for id in unreached.iter() {
+        let node = &data.functions[id];
+        match node.span {
+            Some(ref span) => writeln!(out, "{}: {}", span, node.name)?,
+            None => writeln!(out, "{}", node.name)?,
+        }
+    }
+    writeln!(out, "{}: {} unreachable function(s)", data.crate_name, unreached.len())
+}
+
+// List each recursive SCC, with the calls between its functions and where
+// they are.
+pub fn recursion<W: Write>(data: &FnData, out: &mut W) -> io::Result<()> {
+    let sccs = data.recursion();
+    for scc in sccs.iter() {
+        let names: Vec<&str> = scc.iter().map(|id| &*data.functions[id].name).collect();
+        if scc.len() == 1 {
+            writeln!(out, "direct recursion: {}", names[0])?;
+        } else {
+            writeln!(out, "mutual recursion: {}", names.join(", "))?;
+        }
+
+        let mut edges: Vec<Edge> = data.edges()
+            .filter(|(from, to, _)| scc.contains(from) && scc.contains(to))
+            .collect();
+        edges.sort_by_key(|&(from, to, _)| (from, to));
+        for edge in edges {
+            let (from, to, kind) = edge;
+            let note = note(kind);
+            for site in data.call_sites(&edge).sites.iter() {
+                writeln!(out, "    {}: {} -> {}{}", site, data.functions[&from].name,
+                         data.functions[&to].name, note)?;
+            }
+        }
+    }
+    writeln!(out, "{}: {} recursive component(s)", data.crate_name, sccs.len())
+}
+
+// List paths from the functions matching from to those matching to, with
+// each call and where it is.
+pub fn paths<W: Write>(data: &FnData,
+                       from: &Pattern,
+                       to: &Pattern,
+                       search: PathSearch,
+                       potential: bool,
+                       out: &mut W)
+                       -> io::Result<()> {
+    let from_ids = data.find(from);
+    let to_ids = data.find(to);
+    for (pattern, ids) in [(from, &from_ids), (to, &to_ids)] {
+        if ids.is_empty() {
+            return writeln!(out, "{}: no function matches `{}`", data.crate_name, pattern);
+        }
+    }
+
+    let paths = match search {
+        PathSearch::Shortest(k) => data.shortest_paths(&from_ids, &to_ids, k, potential),
+        PathSearch::All(max_len) => data.simple_paths(&from_ids, &to_ids, max_len, potential),
+    };
+    for (i, path) in paths.iter().enumerate() {
+        writeln!(out, "path {} ({} call(s)):", i + 1, path.len())?;
+        let (start, _, _) = path[0];
+        writeln!(out, "    {}", data.functions[&start].name)?;
+        for edge in path.iter() {
+            write_call(data, edge, out)?;
+        }
+    }
+    writeln!(out, "{}: {} path(s) from `{}` to `{}`", data.crate_name, paths.len(), from, to)
+}
+
+// List the safe, exported functions which can reach unsafe code, each with a
+// path to a function which makes unsafe calls (see FnData::unsafe_calls).
+pub fn unsafe_reach<W: Write>(data: &FnData, potential: bool, out: &mut W) -> io::Result<()> {
+    let reach = data.unsafe_reach(potential);
+    let unsafe_calls = data.unsafe_calls();
+    for (id, path) in reach.iter() {
+        let node = &data.functions[id];
+        match node.span {
+            Some(ref span) => writeln!(out, "{}: {}", span, node.name)?,
+            None => writeln!(out, "{}", node.name)?,
+        }
+        for edge in path.iter() {
+            write_call(data, edge, out)?;
+        }
+
+        let last = path.last().map_or(*id, |&(_, to, _)| to);
+        for edge in unsafe_calls.iter().filter(|&&(from, _, _)| from == last) {
+            let (_, to, _) = *edge;
+            for site in data.unsafe_sites(edge) {
+                writeln!(out, "    unsafe call to {} at {}", data.functions[&to].name, site)?;
+            }
+        }
+    }
+    writeln!(out, "{}: {} safe exported function(s) reach unsafe code",
+             data.crate_name, reach.len())
+}
+
+// List the functions which can panic, each with a path to where std panics.
+pub fn panic_reach<W: Write>(data: &FnData, potential: bool, out: &mut W) -> io::Result<()> {
+    let reach = data.panic_reach(potential);
+    for (id, path) in reach.iter() {
+        let node = &data.functions[id];
+        match node.span {
+            Some(ref span) => writeln!(out, "{}: {}", span, node.name)?,
+            None => writeln!(out, "{}", node.name)?,
+        }
+        for edge in path.iter() {
+            write_call(data, edge, out)?;
+        }
+    }
+    writeln!(out, "{}: {} function(s) can panic", data.crate_name, reach.len())
+}
+
+// List the functions which must not allocate but can, each with a path to
+// where std allocates.
+pub fn alloc_reach<W: Write>(data: &FnData,
+                             patterns: &[Pattern],
+                             potential: bool,
+                             out: &mut W)
+                             -> io::Result<()> {
+    for pattern in patterns.iter() {
+        if data.find(pattern).is_empty() {
+            writeln!(out, "{}: no function matches `{}`", data.crate_name, pattern)?;
+        }
+    }
+
+    let reach = data.alloc_reach(patterns, potential);
+    for (id, path) in reach.iter() {
+        let node = &data.functions[id];
+        match node.span {
+            Some(ref span) => writeln!(out, "{}: {}", span, node.name)?,
+            None => writeln!(out, "{}", node.name)?,
+        }
+        for edge in path.iter() {
+            write_call(data, edge, out)?;
+        }
+    }
+    writeln!(out, "{}: {} of {} no-alloc function(s) can allocate",
+             data.crate_name, reach.len(), data.no_alloc_fns(patterns).len())
+}
+
+// Write the call edge as a step of a path, with where it is.
+fn write_call<W: Write>(data: &FnData, edge: &Edge, out: &mut W) -> io::Result<()> {
+    let (_, to, kind) = *edge;
+    let note = note(kind);
+    let sites = &data.call_sites(edge).sites;
+    let more = if sites.len() > 1 {
+        format!(" (and {} more)", sites.len() - 1)
+    } else {
+        String::new()
+    };
+    // A graph written by hand (or by another tool) might not have any sites.
+    let site = sites.first().map_or_else(|| "<unknown>".to_owned(), |site| site.to_string());
+    writeln!(out, "    -> {} at {}{}{}", data.functions[&to].name, site, more, note)
+}
+
+// List the functions and calls added (`+`) and removed (`-`) between two graphs.
+pub fn diff<W: Write>(diff: &GraphDiff, out: &mut W) -> io::Result<()> {
+    let data = &diff.data;
+    for (sign, ids) in [("+", &diff.added_fns), ("-", &diff.removed_fns)] {
+        for id in ids.iter() {
+            let node = &data.functions[id];
+            match node.span {
+                Some(ref span) => writeln!(out, "{} fn {} ({})", sign, node.name, span)?,
+                None => writeln!(out, "{} fn {}", sign, node.name)?,
+            }
+        }
+    }
+    for (sign, edges) in [("+", &diff.added_calls), ("-", &diff.removed_calls)] {
+        for &(from, to, kind) in edges.iter() {
+            let note = note(kind);
+            writeln!(out, "{} call {} -> {}{}", sign, data.functions[&from].name,
+                     data.functions[&to].name, note)?;
+        }
+    }
+    writeln!(out, "{}: {} function(s) added, {} removed, {} call(s) added, {} removed",
+             data.crate_name, diff.added_fns.len(), diff.removed_fns.len(),
+             diff.added_calls.len(), diff.removed_calls.len())
+}
+
+// How we mark edges which are not Definite calls.
+fn note(kind: CallKind) -> &'static str {
+    match kind {
+        CallKind::Definite => "",
+        CallKind::Potential => " (potential)",
+        CallKind::Contains => " (contains)",
+        CallKind::AddressTaken => " (address taken)",
+    }
+}
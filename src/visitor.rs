@@ -1,309 +1,635 @@
-use rls_data::{DefKind,RefKind};
-use rustc_save_analysis::{self, SaveContext};
-use rustc_save_analysis as save;
-// use rustc::hir::def_id::{DefId,LOCAL_CRATE};
+use rustc_hir as hir;
+use rustc_hir::def::DefKind;
+use rustc_hir::def_id::{DefId, LocalDefId, LOCAL_CRATE};
+use rustc_hir::intravisit::{self, Visitor};
+use rustc_middle::hir::nested_filter;
+use rustc_middle::ty::{self, Ty, TyCtxt};
+use rustc_span::{sym, Pos, Span, Symbol};
+use rustc_target::spec::abi::Abi;
 
-use syntax::ast;
-use syntax::visit;
+use std::collections::HashMap;
 
-// use syntax::source_map::Span;
+use crate::fndata::{CallKind, CallSyntax, Calls, FnData, FnKind, FnNode, FnPath, FnVisibility, Id, SourceLoc};
 
-use std::collections::{HashSet,HashMap};
-
-use crate::fndata::FnData;
-
-pub struct FnVisitor<'l, 'tcx: 'l> {
-    // Used by the save-analysis API.
-    save_cx: SaveContext<'l, 'tcx>,
+// Collects the functions and methods of a crate from its HIR. Calls between
+// them are found in the MIR by mir::collect_calls and recorded with record_call.
+pub struct FnVisitor<'tcx> {
+    tcx: TyCtxt<'tcx>,
 
     // Track statically dispatched function calls.
-    static_calls: HashSet<(rls_data::Id, rls_data::Id)>,
+    static_calls: Calls,
     // (caller def, callee decl).
-    dynamic_calls: HashSet<(rls_data::Id, rls_data::Id)>,
-    // Track function definitions.
-    functions: HashMap<rls_data::Id, String>,
+    dynamic_calls: Calls,
+    // Track function definitions, and functions from other crates which we call.
+    functions: HashMap<Id, FnNode>,
     // Track method declarations.
-    method_decls: HashMap<rls_data::Id, String>,
+    method_decls: HashMap<Id, String>,
     // Maps a method decl to its implementing methods.
-    method_impls: HashMap<rls_data::Id, Vec<rls_data::Id>>,
+    method_impls: HashMap<Id, Vec<Id>>,
+    // Calls in these are marked as unsafe.
+    unsafe_blocks: UnsafeBlocks,
+    // Closures, async blocks, consts, and statics, which are contained in the
+    // function (etc.) they are defined in, if any.
+    contained: Vec<DefId>,
+    // References to functions which are not calls, (referrer, function).
+    address_taken: Calls,
+    // The signatures (see sig_key) of the functions whose address is taken.
+    taken_sigs: HashMap<Id, Vec<SigKey<'tcx>>>,
+    // Calls through fn pointers, (caller, signature, site, syntax, in an unsafe
+    // block).
+    fn_ptr_calls: Vec<(Id, SigKey<'tcx>, SourceLoc, CallSyntax, bool)>,
+}
+
+// The argument and return types of a function signature, with lifetimes
+// erased. A fn pointer might point to any function whose address is taken with
+// the same key.
+type SigKey<'tcx> = &'tcx ty::List<Ty<'tcx>>;
 
-    // Which function we're calling from, we'll update this as we walk the AST.
-    cur_fn: Option<rls_data::Id>,
+fn sig_key<'tcx>(tcx: TyCtxt<'tcx>, sig: ty::PolyFnSig<'tcx>) -> SigKey<'tcx> {
+    tcx.erase_regions(tcx.instantiate_bound_regions_with_erased(sig)).inputs_and_output
 }
 
-// `this.cur_fn.is_some()` or returns.
-macro_rules! ensure_cur_fn {($this: expr, $span: expr) => {
-    if $this.cur_fn.is_none() {
-        println!("WARNING: call at {:?} without known current function",
-                 $span);
+// Return if we're in generated code (i.e., the result of macro expansion or
+// desugaring).
+macro_rules! skip_generated_code {($span: expr) => {
+    if $span.from_expansion() || $span.is_dummy() {
         return;
     }
 }}
 
-// Backup self.cur_fn, set cur_fn to id, continue to walk the AST by executing
-// $walk, then restore self.cur_fn.
-macro_rules! push_walk_pop {($this: expr, $id: expr, $walk: expr) => {{
-    let prev_fn = $this.cur_fn;
-    $this.cur_fn = Some($id);
-    $walk;
-    $this.cur_fn = prev_fn;
-}}}
-
-// Return if we're in generated code.
-// Note: rustc_save_analysis::generated_code is priavte, so include the equivalent code
-// See https://doc.rust-lang.org/nightly/nightly-rustc/rustc_save_analysis/fn.generated_code.html
-macro_rules! skip_generated_code {($span: expr) => {
-    if $span.ctxt() != syntax_pos::NO_EXPANSION || $span.is_dummy() {
-        return;
+// The Id we use for the item identified by def_id.
+pub fn id_from_def_id(def_id: DefId) -> Id {
+    Id {
+        krate: def_id.krate.as_u32(),
+        index: def_id.index.as_u32(),
     }
-}}
+}
 
-// True if the def_id refers to an item in the current crate.
-fn is_local(id: rls_data::Id) -> bool {
-    id.krate == 0
+// The crate-independent identity of def_id.
+pub fn fn_path(tcx: TyCtxt<'_>, def_id: DefId) -> FnPath {
+    FnPath {
+        krate: tcx.crate_name(def_id.krate).to_string(),
+        disambiguator: tcx.stable_crate_id(def_id.krate).as_u64(),
+        def_path: tcx.def_path(def_id).to_string_no_crate_verbose(),
+    }
+}
+
+// The file, line, and column of the start of span. For code expanded from a
+// macro (or desugaring), that is where the outermost macro is called, rather
+// than somewhere in its definition, which may be in another crate.
+pub fn source_loc(tcx: TyCtxt<'_>, span: Span) -> SourceLoc {
+    let loc = tcx.sess.source_map().lookup_char_pos(span.source_callsite().lo());
+    SourceLoc {
+        file: loc.file.name.prefer_local().to_string(),
+        line: loc.line,
+        column: loc.col.to_usize() + 1,
+    }
 }
 
+// The module def_id is defined in, skipping any functions (etc.) in between.
+fn parent_module(tcx: TyCtxt<'_>, def_id: DefId) -> DefId {
+    if let Some(def_id) = def_id.as_local() {
+        return tcx.parent_module_from_def_id(def_id).to_def_id();
+    }
+    let mut module = tcx.parent(def_id);
+    while tcx.def_kind(module) != DefKind::Mod {
+        module = tcx.parent(module);
+    }
+    module
+}
+
+// True if def_id is a local item written with a visibility, e.g., `pub(crate)`.
+fn has_vis(tcx: TyCtxt<'_>, def_id: DefId) -> bool {
+    let Some(def_id) = def_id.as_local() else {
+        return false;
+    };
+    let vis_span = match tcx.hir_node_by_def_id(def_id) {
+        hir::Node::Item(item) => item.vis_span,
+        hir::Node::ImplItem(item) => item.vis_span,
+        hir::Node::ForeignItem(item) => item.vis_span,
+        _ => return false,
+    };
+    !vis_span.is_empty()
+}
+
+// What we know about the function (or closure, const, etc.) def_id, shown as
+// name.
+pub fn fn_node(tcx: TyCtxt<'_>, def_id: DefId, name: String) -> FnNode {
+    let visibility = match tcx.visibility(def_id) {
+        ty::Visibility::Public => FnVisibility::Public,
+        // At the crate root, private and `pub(crate)` mean the same, so we look
+        // at how it is written.
+        ty::Visibility::Restricted(m)
+            if m == parent_module(tcx, def_id) && !(m.is_crate_root() && has_vis(tcx, def_id)) =>
+        {
+            FnVisibility::Private
+        }
+        ty::Visibility::Restricted(m) if m.is_crate_root() => FnVisibility::Crate,
+        ty::Visibility::Restricted(_) => FnVisibility::Restricted,
+    };
+    let def_kind = tcx.def_kind(def_id);
+    // Closures, consts, and statics don't have a fn_sig.
+    let sig = match def_kind {
+        DefKind::Closure | DefKind::Const | DefKind::AssocConst | DefKind::Static { .. } => None,
+        _ => Some(tcx.fn_sig(def_id).skip_binder()),
+    };
+    let abi = match sig.map(|sig| sig.abi()) {
+        None | Some(Abi::Rust | Abi::RustCall | Abi::RustIntrinsic) => None,
+        Some(abi) => Some(abi.name().to_string()),
+    };
+    // The own generics of closures and async blocks are all made up by the
+    // compiler (`<closure_kind>`, `<upvars>`, etc.), as is the host effect
+    // param of const fns.
+    let generics = match def_kind {
+        DefKind::Closure => vec![],
+        _ => tcx.generics_of(def_id).params.iter()
+            .filter(|param| {
+                !matches!(param.kind, ty::GenericParamDefKind::Const { is_host_effect: true, .. })
+            })
+            .map(|param| param.name.to_string())
+            .collect(),
+    };
+
+    let local = def_id.as_local();
+
+    FnNode {
+        name,
+        path: fn_path(tcx, def_id),
+        external: !def_id.is_local(),
+        kind: fn_kind(tcx, def_id),
+        module: fn_path(tcx, parent_module(tcx, def_id)).def_path,
+        span: Some(source_loc(tcx, tcx.def_span(def_id))),
+        visibility,
+        is_unsafe: sig.map_or(false, |sig| sig.unsafety() == hir::Unsafety::Unsafe),
+        is_async: tcx.asyncness(def_id).is_async(),
+        is_const: tcx.is_const_fn_raw(def_id),
+        abi,
+        generics,
+        entry: tcx.entry_fn(()).map_or(false, |(main, _)| main == def_id),
+        test: local.map_or(false, |def_id| def_kind == DefKind::Fn && is_test(tcx, def_id)),
+        exported: local.map_or(false, |def_id| {
+            tcx.effective_visibilities(()).is_exported(def_id)
+        }),
+        no_mangle: local.map_or(false, |def_id| {
+            def_kind.has_codegen_attrs() && tcx.codegen_fn_attrs(def_id).contains_extern_indicator()
+        }),
+        panic: is_panic(tcx, def_id),
+        alloc: is_alloc(tcx, def_id),
+        no_alloc: local.map_or(false, |def_id| {
+            let path = [Symbol::intern("callgraph"), Symbol::intern("no_alloc")];
+            tcx.get_attrs_unchecked(def_id.to_def_id()).iter().any(|a| a.path_matches(&path))
+        }),
+    }
+}
+
+// True if def_id is one of the places std panics from: anything in the
+// `panicking` module of core or std (called by `panic!`, `unreachable!`,
+// bounds and overflow checks, and so on), the Index and IndexMut impls of other
+// crates (e.g., indexing a Vec, HashMap, or str out of range), and the methods
+// of std types which panic on a bad argument or state, such as `unwrap`,
+// `RefCell::borrow_mut`, or `Vec::remove`. We don't see the calls made by other
+// crates' functions, so without these we wouldn't know that calls to them
+// panic.
+fn is_panic(tcx: TyCtxt<'_>, def_id: DefId) -> bool {
+    if def_id.is_local() {
+        return false;
+    }
+    let impl_id = tcx.impl_of_method(def_id);
+    let trait_id = impl_id.and_then(|i| tcx.trait_id_of_impl(i));
+    if trait_id.is_some()
+        && (trait_id == tcx.lang_items().index_trait()
+            || trait_id == tcx.lang_items().index_mut_trait())
+    {
+        return true;
+    }
+
+    let krate = tcx.crate_name(def_id.krate);
+    if krate != sym::core && krate != sym::alloc && krate != sym::std {
+        return false;
+    }
+    let module = tcx.def_path(def_id).data.first().and_then(|d| d.data.get_opt_name());
+    if module.map_or(false, |m| m.as_str() == "panicking") {
+        return true;
+    }
 
+    let Some(impl_id) = impl_id else {
+        return false;
+    };
+    let is = |adt: ty::AdtDef<'_>, name| tcx.is_diagnostic_item(name, adt.did());
+    let methods: &[&str] = match *tcx.type_of(impl_id).instantiate_identity().kind() {
+        ty::Adt(adt, _) if is(adt, sym::Option) => &["unwrap", "expect"],
+        ty::Adt(adt, _) if is(adt, sym::Result) => &["unwrap", "expect", "unwrap_err", "expect_err"],
+        ty::Adt(adt, _) if is(adt, sym::Vec) => &["remove", "swap_remove", "insert", "split_off", "drain"],
+        ty::Adt(adt, _) if is(adt, sym::RefCell) => &["borrow", "borrow_mut"],
+        ty::Slice(_) => &["split_at", "split_at_mut", "copy_from_slice", "clone_from_slice", "swap",
+                          "chunks", "chunks_exact", "windows", "rotate_left", "rotate_right"],
+        _ => &[],
+    };
+    methods.contains(&tcx.item_name(def_id).as_str())
+}
 
+// True if def_id is a `#[test]` function. The test harness replaces the
+// attribute with a const of the same name in the same module, which has a
+// `#[rustc_test_marker]` attribute (this is how Clippy finds tests too).
+fn is_test(tcx: TyCtxt<'_>, def_id: LocalDefId) -> bool {
+    let name = tcx.item_name(def_id.to_def_id());
+    let module = tcx.parent_module_from_def_id(def_id);
+    tcx.hir_module_items(module).free_items().any(|item| {
+        let item = item.owner_id.to_def_id();
+        tcx.def_kind(item) == DefKind::Const
+            && tcx.item_name(item) == name
+            && tcx.has_attr(item, sym::rustc_test_marker)
+    })
+}
 
+// True if def_id is one of the places std allocates from: anything in the
+// `alloc` module of the alloc crate (including `__rust_alloc` and friends, and
+// the allocation for `Box::new` and `vec!`), `format!`, `to_string`, the
+// methods (and Clone, From, and FromIterator impls) of Box, Vec, and String
+// which create or grow them, and the methods of slices and str which the alloc
+// crate adds (`to_vec`, `to_owned`, `to_uppercase`, `sort`, `join`, etc.).
+fn is_alloc(tcx: TyCtxt<'_>, def_id: DefId) -> bool {
+    if tcx.crate_name(def_id.krate) != sym::alloc {
+        return false;
+    }
+    let module = tcx.def_path(def_id).data.first().and_then(|d| d.data.get_opt_name());
+    let name = tcx.item_name(def_id);
+    match module.as_ref().map(|m| m.as_str()) {
+        Some("alloc") => return true,
+        Some("fmt") if name.as_str() == "format" => return true,
+        _ => {}
+    }
+    if tcx.def_kind(def_id) != DefKind::AssocFn {
+        return false;
+    }
 
+    const BOX_METHODS: [&str; 6] = ["new", "pin", "clone", "clone_from", "from", "from_iter"];
+    const VEC_METHODS: [&str; 15] = ["with_capacity", "push", "insert", "reserve", "reserve_exact",
+                                     "extend_from_slice", "extend_from_within", "resize",
+                                     "resize_with", "append", "extend", "clone", "clone_from",
+                                     "from", "from_iter"];
+    const STRING_METHODS: [&str; 12] = ["from", "with_capacity", "push", "push_str", "insert",
+                                        "insert_str", "reserve", "reserve_exact", "extend",
+                                        "clone", "clone_from", "from_iter"];
+    // These only convert a Box, the rest of the alloc crate's slice and str
+    // methods create a Vec or String (or sort with a buffer).
+    const SLICE_CONVERSIONS: [&str; 3] = ["into_vec", "into_string", "into_boxed_bytes"];
+    let trait_id = tcx.trait_of_item(def_id)
+        .or_else(|| tcx.impl_of_method(def_id).and_then(|i| tcx.trait_id_of_impl(i)));
+    if trait_id.map_or(false, |t| tcx.is_diagnostic_item(sym::ToString, t)) {
+        return true;
+    }
+    let Some(impl_id) = tcx.impl_of_method(def_id) else {
+        return false;
+    };
+    let methods: &[&str] = match *tcx.type_of(impl_id).instantiate_identity().kind() {
+        ty::Adt(adt, _) if adt.is_box() => &BOX_METHODS,
+        ty::Adt(adt, _) if tcx.is_diagnostic_item(sym::Vec, adt.did()) => &VEC_METHODS,
+        ty::Adt(adt, _) if tcx.lang_items().string() == Some(adt.did()) => &STRING_METHODS,
+        ty::Slice(_) | ty::Str => return !SLICE_CONVERSIONS.contains(&name.as_str()),
+        _ => return false,
+    };
+    methods.contains(&name.as_str())
+}
 
+fn fn_kind(tcx: TyCtxt<'_>, def_id: DefId) -> FnKind {
+    match tcx.def_kind(def_id) {
+        DefKind::AssocFn => {}
+        DefKind::Closure if tcx.coroutine_kind(def_id).is_some() => return FnKind::AsyncBlock,
+        DefKind::Closure => return FnKind::Closure,
+        DefKind::Const | DefKind::AssocConst => return FnKind::Const,
+        DefKind::Static { .. } => return FnKind::Static,
+        _ => return FnKind::FreeFn,
+    }
+    let item = tcx.associated_item(def_id);
+    match item.container {
+        ty::AssocItemContainer::TraitContainer if item.defaultness(tcx).has_value() => {
+            FnKind::TraitDefaultMethod
+        }
+        ty::AssocItemContainer::TraitContainer => FnKind::TraitMethod,
+        ty::AssocItemContainer::ImplContainer if item.trait_item_def_id.is_some() => {
+            FnKind::TraitImplMethod
+        }
+        ty::AssocItemContainer::ImplContainer => FnKind::InherentMethod,
+    }
+}
 
-impl<'l, 'tcx: 'l> FnVisitor<'l, 'tcx> {
-    pub fn new(save_cx: SaveContext<'l, 'tcx>) -> FnVisitor<'l, 'tcx> {
+// True if the def_id refers to an item in the current crate.
+fn is_local(id: Id) -> bool {
+    id.krate == LOCAL_CRATE.as_u32()
+}
+
+impl<'tcx> FnVisitor<'tcx> {
+    pub fn new(tcx: TyCtxt<'tcx>) -> FnVisitor<'tcx> {
         FnVisitor{
-            save_cx,
-            static_calls: HashSet::new(),
-            dynamic_calls: HashSet::new(),
+            tcx,
+            static_calls: Calls::new(),
+            dynamic_calls: Calls::new(),
             functions: HashMap::new(),
             method_decls: HashMap::new(),
             method_impls: HashMap::new(),
-            cur_fn: None
+            unsafe_blocks: UnsafeBlocks::collect(tcx),
+            contained: vec![],
+            address_taken: Calls::new(),
+            taken_sigs: HashMap::new(),
+            fn_ptr_calls: vec![],
         }
     }
 
     pub fn post_process(self, crate_name: &str) -> FnData {
-        let mut processed_calls = HashSet::new();
-        let mut processed_fns = HashMap::with_capacity(self.functions.len());
-
-        for &(ref from, ref to) in self.dynamic_calls.iter() {
-            for to in self.method_impls[to].iter() {
-                processed_calls.insert((*from, *to));
-                self.append_fn(&mut processed_fns, *from);
-                self.append_fn(&mut processed_fns, *to);
+        let mut processed_calls = Calls::new();
+
+        for ((from, to), sites) in self.dynamic_calls.iter() {
+            // A call to a method of a trait from another crate might also go
+            // to an implementation in another crate, we don't know which, so
+            // keep the call to the decl.
+            let external_decl = match self.functions.get(to) {
+                Some(node) if node.external => Some(to),
+                _ => None,
+            };
+            let impls = self.method_impls.get(to).into_iter().flatten();
+            for to in impls.chain(external_decl) {
+                processed_calls.entry((*from, *to)).or_default().extend(sites);
             }
         }
-        if super::SKIP_UNCONNECTED_FNS {
-            for &(ref from, ref to) in self.static_calls.iter() {
-                self.append_fn(&mut processed_fns, *from);
-                self.append_fn(&mut processed_fns, *to);
+
+        // A call through a fn pointer is a Potential call to every function
+        // whose address is taken with the same signature.
+        for (from, sig, site, syntax, in_unsafe) in self.fn_ptr_calls.iter() {
+            for (to, sigs) in self.taken_sigs.iter() {
+                if sigs.contains(sig) {
+                    let sites = processed_calls.entry((*from, *to)).or_default();
+                    sites.add_call(site.clone(), *syntax, *in_unsafe);
+                }
             }
         }
+
+        // The parent of a closure etc. is the nearest node it is nested in.
+        let mut contains = Calls::new();
+        for &def_id in self.contained.iter() {
+            let parent = std::iter::successors(self.tcx.opt_parent(def_id), |&p| self.tcx.opt_parent(p))
+                .map(id_from_def_id)
+                .find(|id| self.functions.contains_key(id));
+            if let Some(parent) = parent {
+                let site = source_loc(self.tcx, self.tcx.def_span(def_id));
+                contains.entry((parent, id_from_def_id(def_id))).or_default().add(site, false);
+            }
+        }
+
         FnData {
             static_calls: self.static_calls,
             dynamic_calls: processed_calls,
-            functions: if super::SKIP_UNCONNECTED_FNS {
-                    processed_fns
-                } else {
-                    self.functions
-                },
+            contains,
+            address_taken: self.address_taken,
+            functions: self.functions,
             crate_name: crate_name.to_string()
         }
     }
 
-    // If we are skipping unconnected functions, then keep track of which
-    // functions are connected.
-    fn append_fn(&self, map: &mut HashMap<rls_data::Id, String>, id: rls_data::Id) {
-        if !super::SKIP_UNCONNECTED_FNS {
-            return;
-        }
+    // The name we use for a function in the output.
+    fn qualname(&self, def_id: DefId) -> String {
+        self.tcx.def_path_str(def_id)
+    }
+
+    // True if id is a function we are including in the graph.
+    pub fn is_function(&self, id: Id) -> bool {
+        self.functions.contains_key(&id)
+    }
 
-        if map.contains_key(&id) {
+    // Record a call from the function from to def_id. A Definite call is to
+    // the def of the callee. A Potential call is to the decl of a trait method
+    // where we don't know the receiver type; it may go to any of the decl's
+    // implementations, so is left for post_process to fan out.
+    pub fn record_call(&mut self,
+                       from: Id,
+                       def_id: DefId,
+                       kind: CallKind,
+                       syntax: CallSyntax,
+                       span: Span) {
+        let to = id_from_def_id(def_id);
+        let site = source_loc(self.tcx, span);
+        let in_unsafe = self.unsafe_blocks.contains(span);
+        if !is_local(to) {
+            self.record_external_fn(def_id);
+            let calls = match kind {
+                CallKind::Definite => &mut self.static_calls,
+                CallKind::Potential => &mut self.dynamic_calls,
+                CallKind::Contains | CallKind::AddressTaken => unreachable!("not a call"),
+            };
+            calls.entry((from, to)).or_default().add_call(site, syntax, in_unsafe);
             return;
         }
-        eprintln!("id: {:?}", id);
-        debug_assert!(self.functions.contains_key(&id));
 
-        map.insert(id, self.functions[&id].clone());
+        match kind {
+            CallKind::Definite if self.functions.contains_key(&to) => {
+                self.static_calls.entry((from, to)).or_default().add_call(site, syntax, in_unsafe);
+            }
+            CallKind::Potential if self.method_decls.contains_key(&to) => {
+                self.dynamic_calls.entry((from, to)).or_default().add_call(site, syntax, in_unsafe);
+            }
+            _ => {}
+        }
     }
 
-    // Record that def implements decl.
-    fn append_method_impl(&mut self, decl: rls_data::Id, def: rls_data::Id) {
-        if !self.method_impls.contains_key(&decl) {
-            self.method_impls.insert(decl, vec![]);
+    // Record that from refers to def_id without calling it (e.g., takes its
+    // address), as a function with signature sig.
+    pub fn record_address_taken(&mut self,
+                                from: Id,
+                                def_id: DefId,
+                                sig: ty::PolyFnSig<'tcx>,
+                                span: Span) {
+        // Struct and variant constructors (e.g., `map(Some)`) are not nodes.
+        if let DefKind::Ctor(..) = self.tcx.def_kind(def_id) {
+            return;
+        }
+        let to = id_from_def_id(def_id);
+        if !is_local(to) {
+            self.record_external_fn(def_id);
+        } else if !self.functions.contains_key(&to) {
+            return;
+        }
+        let site = source_loc(self.tcx, span);
+        self.address_taken.entry((from, to)).or_default().add(site, false);
+        let sig = sig_key(self.tcx, sig);
+        let sigs = self.taken_sigs.entry(to).or_default();
+        if !sigs.contains(&sig) {
+            sigs.push(sig);
         }
-
-        self.method_impls.get_mut(&decl).unwrap().push(def);
     }
 
-}
+    // Record a call from the function from through a fn pointer with
+    // signature sig. We match these to functions whose address is taken in
+    // post_process.
+    pub fn record_fn_ptr_call(&mut self,
+                              from: Id,
+                              sig: ty::PolyFnSig<'tcx>,
+                              syntax: CallSyntax,
+                              span: Span) {
+        let site = source_loc(self.tcx, span);
+        let in_unsafe = self.unsafe_blocks.contains(span);
+        self.fn_ptr_calls.push((from, sig_key(self.tcx, sig), site, syntax, in_unsafe));
+    }
 
-// See https://doc.rust-lang.org/nightly/nightly-rustc/syntax/visit/trait.Visitor.html
-//
-impl<'v, 'l, 'tcx: 'l> visit::Visitor<'v> for FnVisitor<'l, 'tcx> {
-    // Visit a path - the path could point to a function or method.
-    fn visit_path(&mut self, path: &'v ast::Path, id: ast::NodeId) {
-        eprintln!("visit_path id={:?}", id);
-        // eprintln!("path: {:?}", path);
-        // eprintln!("id: {:?}", id);
-        skip_generated_code!(path.span);
-
-        let data = self.save_cx.get_path_data(id, path);
-        eprintln!("    get_path_data {:?}", data);
-        // eprintln!("data: {:?}", data);
-        if let Some(ref rfd) = data {
-            eprintln!("    rfd: {:?}", rfd);
-            if rfd.kind == RefKind::Function {
-                if is_local(rfd.ref_id) {
-                    let to = rfd.ref_id;
-                    ensure_cur_fn!(self, rfd.span);
-                    eprintln!("***  {:?} -> {:?} ***", self.cur_fn.unwrap(), to);
-                    eprintln!("  path: {:?}", path);
-                    eprintln!("  span: {:?}", path.span);
-                    self.static_calls.insert((self.cur_fn.unwrap(), to));
-                } else {
-                    eprintln!("NOT local");
-                }
-            }
-            // if let Some(save::Data::MethodCallData(ref mrd)) = data {
-            //     self.record_method_call(mrd);
-            // }
+    // Record a function from another crate, the first time it is called.
+    fn record_external_fn(&mut self, def_id: DefId) {
+        let id = id_from_def_id(def_id);
+        if !self.functions.contains_key(&id) {
+            self.record_fn(id, def_id);
         }
-
-        // Continue walking the AST.
-        visit::walk_path(self, path)
     }
 
-    // fn visit_fn(&mut self, fk: visit::FnKind<'v>, fd: &'v ast::FnDecl, s: Span, _: ast::NodeId) {
-    //     eprintln!("visit_fn {:?}", fd);
-    //     visit::walk_fn(self, fk, fd, s)
-    // }
-
-    // Visit an expression
-    fn visit_expr(&mut self, ex: &'v ast::Expr) {
-        skip_generated_code!(ex.span);
+    // Record a function definition.
+    fn record_fn(&mut self, id: Id, def_id: DefId) {
+        let node = fn_node(self.tcx, def_id, self.qualname(def_id));
+        self.functions.insert(id, node);
+    }
 
-        visit::walk_expr(self, ex);
+    // Record a closure, async block, const, or static.
+    fn record_contained(&mut self, def_id: DefId) {
+        self.record_fn(id_from_def_id(def_id), def_id);
+        self.contained.push(def_id);
+    }
 
-        eprintln!("visit_expr: {:?}", ex);
-        eprintln!("  node: {:?}", ex.node);
+    // Record that def implements decl.
+    fn append_method_impl(&mut self, decl: Id, def: Id) {
+        self.method_impls.entry(decl).or_default().push(def);
+    }
 
-        eprintln!("  get_expr_data: {:?}", self.save_cx.get_expr_data(ex));
-        if let ast::ExprKind::Call(ref _f, ref _args) = ex.node {
-            eprintln!("    Call");
-            eprintln!("    node: {:?}", ex.node);
-            eprintln!("    f:    {:?}", _f);
-            eprintln!("        get_expr_data: {:?}", self.save_cx.get_expr_data(_f));
-            eprintln!("    args: {:?}", _args);
-        }
-        if let ast::ExprKind::MethodCall(ref _seg, ref _args) = ex.node {
-            eprintln!("    MethodCall");
-            eprintln!("    node: {:?}", ex.node);
-            eprintln!("    seg:  {:?}", _seg);
-            eprintln!("        get_path_segment_data: {:?}", self.save_cx.get_path_segment_data(_seg));
-        }
+}
 
-        // Skip everything except method calls. (We shouldn't have to do this, but
-        // calling get_expr_data on an expression it doesn't know about will panic).
-        // if let ast::ExprKind::MethodCall(..) = ex.node {} else {
-        //     return;
-        // }
+// See https://doc.rust-lang.org/nightly/nightly-rustc/rustc_hir/intravisit/trait.Visitor.html
+//
+// We walk into bodies only to find closures and async blocks. Nested items are
+// visited separately by visit_all_item_likes_in_crate.
+impl<'tcx> Visitor<'tcx> for FnVisitor<'tcx> {
+    type NestedFilter = nested_filter::OnlyBodies;
 
-        let data = self.save_cx.get_expr_data(ex);
-        dbg!(&data);
-        if data.is_some() { unimplemented!(); }
-        // if let Some(save::Data::MethodCallData(ref mrd)) = data {
-        //     self.record_method_call(mrd);
-        // }
+    fn nested_visit_map(&mut self) -> Self::Map {
+        self.tcx.hir()
     }
 
-    fn visit_item(&mut self, item: &'v ast::Item) {
-        eprintln!("visit_item ident={:?}", item.ident);
+    fn visit_item(&mut self, item: &'tcx hir::Item<'tcx>) {
         skip_generated_code!(item.span);
-        if let ast::ItemKind::Fn(..) = item.node {
-            // eprintln!("Got function item for {}", item.ident.to_string());
-            let data = self.save_cx.get_item_data(item);
-            // eprintln!("data: {:?}", data);
-            if let Some(ref d) = data {
-                // eprintln!("d: {:?}", d);
-                if let save::Data::DefData(ref fd) = d {
-                    if fd.kind == DefKind::Function {
-                        // dbg!(&fd);
-                        eprintln!("***  defining function {}: {:?} ***", fd.qualname, fd.id);
-                        self.functions.insert(fd.id, fd.qualname.clone());
-                        // eprintln!("***  {} -> {} ***", from, to);
-                        push_walk_pop!(self, fd.id, visit::walk_item(self, item));
-                        return;
-                    }
-                }
-                if let save::Data::RelationData(ref rel, ref im) = d {
-                    eprintln!("RelationData");
-                }
-                if let save::Data::RefData(ref fd) = d {
-                    eprintln!("RefData");
-                }
+        match item.kind {
+            hir::ItemKind::Fn(..) => {
+                let def_id = item.owner_id.to_def_id();
+                let id = id_from_def_id(def_id);
+                self.record_fn(id, def_id);
+            }
+            hir::ItemKind::Const(..) | hir::ItemKind::Static(..) => {
+                self.record_contained(item.owner_id.to_def_id());
+            }
+            _ => {}
+        }
+        intravisit::walk_item(self, item)
+    }
+
+    fn visit_expr(&mut self, expr: &'tcx hir::Expr<'tcx>) {
+        // Generated code can contain code from the source (e.g., the body of a
+        // `for` loop or the arguments of a macro), so we walk it, but only
+        // record the closures which are written in the source.
+        let generated = expr.span.from_expansion() || expr.span.is_dummy();
+        if let hir::ExprKind::Closure(closure) = expr.kind {
+            // The body of an async fn is an async block, but we treat it as
+            // part of the function.
+            let def_id = closure.def_id.to_def_id();
+            if !generated
+                && !matches!(self.tcx.coroutine_kind(def_id),
+                             Some(hir::CoroutineKind::Desugared(_, hir::CoroutineSource::Fn))) {
+                self.record_contained(def_id);
             }
         }
-        visit::walk_item(self, item)
+        intravisit::walk_expr(self, expr)
     }
 
-    fn visit_trait_item(&mut self, ti: &'v ast::TraitItem) {
+    fn visit_trait_item(&mut self, ti: &'tcx hir::TraitItem<'tcx>) {
         skip_generated_code!(ti.span);
-        // Note to self: it is kinda sucky we have to examine the AST before
-        // asking for data here.
-        match ti.node {
+        let def_id = ti.owner_id.to_def_id();
+        let id = id_from_def_id(def_id);
+        match ti.kind {
             // A method declaration.
-            ast::TraitItemKind::Method(_, None) => {
-                let fd = self.save_cx.get_method_data(ti.id, ti.ident, ti.span).expect("get_method_data");
-                self.method_decls.insert(fd.id, fd.qualname);
-                self.method_impls.insert(fd.id, vec![]);
+            hir::TraitItemKind::Fn(_, hir::TraitFn::Required(_)) => {
+                self.method_decls.insert(id, self.qualname(def_id));
+                self.method_impls.entry(id).or_default();
             }
             // A default method. This declares a trait method and provides an
             // implementation.
-            ast::TraitItemKind::Method(_, Some(_)) => {
-                let fd = self.save_cx.get_method_data(ti.id, ti.ident, ti.span).expect("get_method_data");
+            hir::TraitItemKind::Fn(_, hir::TraitFn::Provided(_)) => {
+                let qualname = self.qualname(def_id);
                 // Record, a declaration, a definintion, and a reflexive implementation.
-                eprintln!("***  defining method {}: {:?} ***", fd.qualname, fd.id);
-                self.method_decls.insert(fd.id, fd.qualname.clone());
-                self.functions.insert(fd.id, fd.qualname);
-                self.append_method_impl(fd.id, fd.id);
-                push_walk_pop!(self, fd.id, visit::walk_trait_item(self, ti));
-
-                return;
+                self.method_decls.insert(id, qualname);
+                self.record_fn(id, def_id);
+                self.append_method_impl(id, id);
             }
+            // An associated const with a default.
+            hir::TraitItemKind::Const(_, Some(_)) => self.record_contained(def_id),
             _ => {}
         }
 
-        visit::walk_trait_item(self, ti)
+        intravisit::walk_trait_item(self, ti)
     }
 
-    fn visit_impl_item(&mut self, ii: &'v ast::ImplItem) {
+    fn visit_impl_item(&mut self, ii: &'tcx hir::ImplItem<'tcx>) {
         skip_generated_code!(ii.span);
 
-        if let ast::ImplItemKind::Method(..) = ii.node {
-            let fd = self.save_cx.get_method_data(ii.id, ii.ident, ii.span).expect("get_method_data");
-            eprintln!("visit_impl_item: {:?} {}", fd.id, fd.qualname);
-            eprintln!("  ii.id: {:?}", ii.id);
-            eprintln!("  fd: {:?}", fd);
+        if let hir::ImplItemKind::Const(..) = ii.kind {
+            self.record_contained(ii.owner_id.to_def_id());
+        }
+        if let hir::ImplItemKind::Fn(..) = ii.kind {
+            let def_id = ii.owner_id.to_def_id();
+            let id = id_from_def_id(def_id);
             // Record the method's existence.
-            eprintln!("***  defining method {}: {:?} ***", fd.qualname, fd.id);
-            self.functions.insert(fd.id, fd.qualname);
-            if let Some(decl) = fd.decl_id {
-                if is_local(decl) {
-                    eprintln!("is_local");
-                    // If we're implementing a method in the local crate, record
-                    // the implementation of the decl.
-                    self.append_method_impl(decl, fd.id);
-                }
+            self.record_fn(id, def_id);
+            // Record the implementation of the decl, even for traits from
+            // other crates, since calls to those can dispatch to us too.
+            if let Some(decl) = self.tcx.associated_item(def_id).trait_item_def_id {
+                self.append_method_impl(id_from_def_id(decl), id);
             }
+        }
 
-            push_walk_pop!(self, fd.id, visit::walk_impl_item(self, ii));
+        intravisit::walk_impl_item(self, ii)
+    }
+}
 
-            return;
-        }
+// The unsafe blocks written in the crate, not those from macro expansion.
+pub struct UnsafeBlocks {
+    spans: Vec<Span>,
+}
 
-        visit::walk_impl_item(self, ii)
+impl UnsafeBlocks {
+    pub fn collect(tcx: TyCtxt<'_>) -> UnsafeBlocks {
+        let mut visitor = UnsafeBlockVisitor { tcx, spans: vec![] };
+        tcx.hir().walk_toplevel_module(&mut visitor);
+        UnsafeBlocks { spans: visitor.spans }
     }
 
+    // True if the code at span is in an unsafe block. For code from a macro
+    // expansion or desugaring, that is where the macro or sugar was used.
+    pub fn contains(&self, span: Span) -> bool {
+        let span = span.source_callsite();
+        self.spans.iter().any(|block| block.contains(span))
+    }
+}
+
+// Unlike FnVisitor, we need to walk into bodies to find blocks.
+struct UnsafeBlockVisitor<'tcx> {
+    tcx: TyCtxt<'tcx>,
+    spans: Vec<Span>,
+}
 
+impl<'tcx> Visitor<'tcx> for UnsafeBlockVisitor<'tcx> {
+    type NestedFilter = nested_filter::All;
+
+    fn nested_visit_map(&mut self) -> Self::Map {
+        self.tcx.hir()
+    }
+
+    fn visit_block(&mut self, block: &'tcx hir::Block<'tcx>) {
+        if let hir::BlockCheckMode::UnsafeBlock(hir::UnsafeSource::UserProvided) = block.rules {
+            if !block.span.from_expansion() {
+                self.spans.push(block.span);
+            }
+        }
+        intravisit::walk_block(self, block)
+    }
 }
@@ -0,0 +1,182 @@
+// Runs the callgraph driver on the single file crates in tests/fixtures.
+
+use serde_json::Value;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+// A graph written with `--format json`.
+struct Graph(Value);
+
+impl Graph {
+    fn node(&self, name: &str) -> Option<&Value> {
+        self.0["nodes"].as_array().unwrap().iter().find(|n| n["name"] == name)
+    }
+
+    // The edge of kind (e.g., `definite`) between the functions called from
+    // and to.
+    fn edge(&self, from: &str, to: &str, kind: &str) -> Option<&Value> {
+        let (from, to) = (self.node(from)?, self.node(to)?);
+        self.0["edges"].as_array().unwrap().iter()
+            .find(|e| e["from"] == from["id"] && e["to"] == to["id"] && e["kind"] == kind)
+    }
+
+    fn calls(&self, from: &str, to: &str) -> bool {
+        self.edge(from, to, "definite").is_some()
+    }
+}
+
+// Run callgraph with opts on tests/fixtures/file, compiled as a library.
+fn run(file: &str, opts: &[&str]) -> Output {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(file);
+    Command::new(env!("CARGO_BIN_EXE_callgraph"))
+        .args(opts)
+        .arg("--")
+        .arg(path)
+        .args(["--crate-type", "lib", "--edition", "2021", "-A", "warnings"])
+        .current_dir(env!("CARGO_TARGET_TMPDIR"))
+        .output()
+        .expect("could not run callgraph")
+}
+
+// Run callgraph with opts on tests/fixtures/file and return the graph.
+fn callgraph(file: &str, opts: &[&str]) -> Graph {
+    let opts = [opts, &["--format", "json", "-o", "-"]].concat();
+    let output = run(file, &opts);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    Graph(serde_json::from_slice(&output.stdout).unwrap())
+}
+
+#[test]
+fn emit_error() {
+    let output = run("closures.rs", &["-o", "no/such/dir/graph.dot"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("error writing callgraph"));
+}
+
+#[test]
+fn closures_in_generated_code() {
+    // Closures in a loop's body, an async fn, or a macro's arguments are nodes
+    // of their own, so the calls in them are theirs.
+    let graph = callgraph("closures.rs", &[]);
+    for f in ["in_loop", "in_while", "in_async", "in_macro"] {
+        let closure = graph.0["nodes"].as_array().unwrap().iter()
+            .find(|n| n["name"].as_str().unwrap().starts_with(&format!("{}::{{closure", f)))
+            .unwrap_or_else(|| panic!("no closure in {}", f));
+        let closure = closure["name"].as_str().unwrap();
+        assert!(graph.calls(closure, "helper"), "{}", closure);
+        assert!(!graph.calls(f, "helper"), "{}", f);
+        assert!(graph.edge(f, closure, "contains").is_some(), "{}", f);
+    }
+}
+
+#[test]
+fn vtables_of_smart_pointers() {
+    // Unsizing an Rc, an Arc, or a raw pointer creates a vtable, so in mono
+    // mode the function which does it may call the methods in it.
+    let graph = callgraph("unsize.rs", &["--mono"]);
+    for (f, method) in [("rc", "<Square as Shape>::area"), ("arc", "<Circle as Shape>::area"),
+                        ("raw", "<Dot as Shape>::area")] {
+        assert!(graph.edge(f, method, "potential").is_some(), "{} -> {}", f, method);
+    }
+}
+
+#[test]
+fn aggregate_items_in_functions() {
+    let graph = callgraph("nested.rs", &["--aggregate", "module"]);
+    let names: Vec<&str> = graph.0["nodes"].as_array().unwrap().iter()
+        .map(|n| n["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["nested::m"]);
+}
+
+#[test]
+fn calls_in_mir() {
+    let graph = callgraph("mir_calls.rs", &[]);
+    assert!(graph.calls("drops", "<Guard as std::ops::Drop>::drop"));
+    assert!(graph.calls("index", "core::panicking::panic_bounds_check"));
+    assert!(graph.calls("add", "core::panicking::panic_const::panic_const_add_overflow"));
+}
+
+#[test]
+fn trait_method_calls() {
+    // With a known receiver, the call resolves to the impl's method, whether
+    // it is written as a method call or a path.
+    let graph = callgraph("traits.rs", &[]);
+    for f in ["known", "path"] {
+        assert!(graph.calls(f, "<Square as Shape>::area"), "{}", f);
+        assert!(graph.edge(f, "<Circle as Shape>::area", "potential").is_none(), "{}", f);
+    }
+    // Otherwise it may call any impl.
+    for f in ["dynamic", "generic"] {
+        for method in ["<Square as Shape>::area", "<Circle as Shape>::area"] {
+            assert!(graph.edge(f, method, "potential").is_some(), "{} -> {}", f, method);
+            assert!(!graph.calls(f, method), "{} -> {}", f, method);
+        }
+    }
+}
+
+#[test]
+fn panics() {
+    let graph = callgraph("std_calls.rs", &[]);
+    assert_eq!(graph.node("std::option::Option::<T>::unwrap").unwrap()["panic"], true);
+    assert_eq!(graph.node("std::boxed::Box::<T>::new").unwrap()["panic"], false);
+    let graph = callgraph("mir_calls.rs", &[]);
+    assert_eq!(graph.node("core::panicking::panic_bounds_check").unwrap()["panic"], true);
+
+    let out = String::from_utf8(run("std_calls.rs", &["panics"]).stdout).unwrap();
+    assert!(out.contains(": unwraps\n    -> std::option::Option::<T>::unwrap at "), "{}", out);
+    assert!(out.contains("std_calls: 1 function(s) can panic"), "{}", out);
+}
+
+#[test]
+fn allocations() {
+    // collect and into call FromIterator::from_iter and From::from for us.
+    let graph = callgraph("std_calls.rs", &[]);
+    let from_iter = "<std::vec::Vec<T> as std::iter::FromIterator<T>>::from_iter";
+    let from = "<std::string::String as std::convert::From<&str>>::from";
+    assert!(graph.calls("collects", from_iter));
+    assert!(graph.calls("converts", from));
+    for f in ["std::boxed::Box::<T>::new", from_iter, from] {
+        assert_eq!(graph.node(f).unwrap()["alloc"], true, "{}", f);
+    }
+    assert_eq!(graph.node("std::option::Option::<T>::unwrap").unwrap()["alloc"], false);
+
+    let list = Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_alloc.txt");
+    fs::write(&list, "boxes\ncollects\nconverts\nborrows\n").unwrap();
+    let out = run("std_calls.rs", &["no-alloc", "--list", list.to_str().unwrap()]).stdout;
+    let out = String::from_utf8(out).unwrap();
+    for f in ["boxes", "collects", "converts"] {
+        assert!(out.contains(&format!(": {}\n", f)), "{}", out);
+    }
+    assert!(out.contains("std_calls: 3 of 4 no-alloc function(s) can allocate"), "{}", out);
+}
+
+#[test]
+fn call_syntax() {
+    let sites = |graph: &Graph, from, to, syntax: &str| {
+        graph.edge(from, to, "definite").unwrap()[syntax].as_array().unwrap().len()
+    };
+    let graph = callgraph("traits.rs", &[]);
+    assert_eq!(sites(&graph, "known", "<Square as Shape>::area", "method_sites"), 1);
+    assert_eq!(sites(&graph, "path", "<Square as Shape>::area", "method_sites"), 0);
+    assert_eq!(sites(&graph, "path", "<Square as Shape>::area", "implicit_sites"), 0);
+    let graph = callgraph("mir_calls.rs", &[]);
+    assert_eq!(sites(&graph, "drops", "<Guard as std::ops::Drop>::drop", "implicit_sites"), 1);
+}
+
+#[test]
+fn fn_pointers() {
+    // Passing a function isn't calling it, and a call through a fn pointer may
+    // call any function of its type whose address is taken.
+    let graph = callgraph("fn_ptrs.rs", &[]);
+    assert!(graph.edge("uses", "double", "address_taken").is_some());
+    assert!(!graph.calls("uses", "double"));
+    assert!(graph.calls("uses", "triple"));
+    assert!(graph.edge("apply", "double", "potential").is_some());
+    assert!(graph.edge("apply_narrow", "narrow", "potential").is_some());
+    for (f, g) in [("apply", "triple"), ("apply", "narrow"), ("apply_narrow", "double")] {
+        assert!(graph.edge(f, g, "potential").is_none(), "{} -> {}", f, g);
+    }
+}
@@ -0,0 +1,102 @@
+// Runs cargo-callgraph on the packages in tests/fixtures.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Copy the directory from to to, leaving out anything Cargo generated.
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let name = entry.file_name();
+        if name == "target" || name == "Cargo.lock" {
+            continue;
+        }
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &to.join(name));
+        } else {
+            fs::copy(entry.path(), to.join(name)).unwrap();
+        }
+    }
+}
+
+// The target directory of the fixture's package.
+fn target_dir(fixture: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(fixture)
+}
+
+// Run cargo-callgraph with args on the package at path in tests/fixtures. The
+// first component of path is the fixture, which is copied out of this repo
+// first, so that our rust-toolchain.toml doesn't apply to it and it is built as
+// the user's toolchain would build it. Cargo's output goes to the fixture's
+// target_dir.
+fn run(path: &str, args: &[&str]) -> Output {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let fixture = path.split('/').next().unwrap();
+    let run = RUNS.fetch_add(1, Ordering::SeqCst);
+    let dir = env::temp_dir().join(format!("callgraph-{}-{}-{}", fixture, process::id(), run));
+    copy_dir(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(fixture), &dir);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-callgraph"))
+        .arg("callgraph")
+        .args(args)
+        .current_dir(dir.join(path.split_at(fixture.len()).1.trim_start_matches('/')))
+        .env("CARGO_TARGET_DIR", target_dir(fixture))
+        .env_remove("RUSTC")
+        .output()
+        .expect("could not run cargo-callgraph");
+    fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+// Run cargo-callgraph like run, and return its stdout.
+fn cargo_callgraph(path: &str, args: &[&str]) -> String {
+    let output = run(path, args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn merge_lib_and_bin() {
+    // The lib and bin have the same crate name, both graphs must be merged.
+    let out = cargo_callgraph("libbin", &["--merge", "--format", "text", "-o", "-"]);
+    assert!(out.contains("\nmain -> libfn\n"), "{}", out);
+    assert!(out.contains("\nlibfn -> inner\n"), "{}", out);
+}
+
+#[test]
+fn rerun_with_other_options() {
+    // The package is checked again, even though it hasn't changed.
+    let out = cargo_callgraph("libbin", &["callees", "libfn", "--format", "text", "-o", "-"]);
+    assert!(out.contains("\nlibfn -> inner\n"), "{}", out);
+    let out = cargo_callgraph("libbin", &["dead-code"]);
+    assert!(out.contains("libbin: 0 unreachable function(s)"), "{}", out);
+}
+
+#[test]
+fn path_dependency() {
+    // dep is compiled by our toolchain's rustc, not the user's, so the driver
+    // can read it.
+    let out = cargo_callgraph("withdep/app", &["--format", "text", "-o", "-"]);
+    assert!(out.contains("\nrun -> dep::helper\n"), "{}", out);
+}
+
+#[test]
+fn merge_only_this_run() {
+    // A graph left in the target directory by something else isn't merged.
+    let stale = target_dir("libbin").join("callgraph");
+    fs::create_dir_all(&stale).unwrap();
+    fs::write(stale.join("stale-lib.json"), "not a graph").unwrap();
+    let out = cargo_callgraph("libbin", &["--merge", "--format", "text", "-o", "-"]);
+    assert!(out.contains("\nmain -> libfn\n"), "{}", out);
+}
+
+#[test]
+fn output_needs_merge() {
+    let output = run("libbin", &["-o", "graph.dot"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("-o needs --merge"));
+}
@@ -0,0 +1,29 @@
+// Closures in code which the compiler generates around them.
+
+fn helper() -> bool {
+    true
+}
+
+pub fn in_loop(v: Vec<u32>) {
+    for _x in v {
+        let c = || helper();
+        c();
+    }
+}
+
+pub fn in_while(mut n: u32) {
+    while n > 0 {
+        let c = || helper();
+        c();
+        n -= 1;
+    }
+}
+
+pub async fn in_async() {
+    let c = || helper();
+    c();
+}
+
+pub fn in_macro(v: &[u32]) {
+    assert!(v.iter().any(|_| helper()));
+}
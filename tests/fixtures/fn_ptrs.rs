@@ -0,0 +1,27 @@
+// Calls through fn pointers, which may call any function of the same type
+// whose address is taken.
+
+pub fn double(x: u32) -> u32 {
+    x * 2
+}
+
+pub fn triple(x: u32) -> u32 {
+    x * 3
+}
+
+pub fn narrow(x: u8) -> u8 {
+    x
+}
+
+pub fn apply(f: fn(u32) -> u32) -> u32 {
+    f(1)
+}
+
+pub fn apply_narrow(f: fn(u8) -> u8) -> u8 {
+    f(1)
+}
+
+pub fn uses() -> u32 {
+    let _ = apply_narrow(narrow);
+    apply(double) + triple(2)
+}
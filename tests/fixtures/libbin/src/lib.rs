@@ -0,0 +1,7 @@
+pub fn libfn() -> u32 {
+    inner()
+}
+
+fn inner() -> u32 {
+    1
+}
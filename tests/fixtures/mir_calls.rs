@@ -0,0 +1,19 @@
+// Calls which only MIR shows: drops, and bounds and overflow checks.
+
+pub struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {}
+}
+
+pub fn drops() {
+    let _guard = Guard;
+}
+
+pub fn index(v: &[u32], i: usize) -> u32 {
+    v[i]
+}
+
+pub fn add(a: u32, b: u32) -> u32 {
+    a + b
+}
@@ -0,0 +1,21 @@
+// Items inside functions, which are in the module of the function.
+
+pub mod m {
+    pub fn outer() -> u32 {
+        const X: u32 = 1;
+
+        fn inner() -> u32 {
+            X
+        }
+
+        struct S;
+
+        impl S {
+            fn get(&self) -> u32 {
+                inner()
+            }
+        }
+
+        S.get()
+    }
+}
@@ -0,0 +1,22 @@
+// Calls to std functions which panic or allocate, including through collect
+// and into.
+
+pub fn unwraps(x: Option<u32>) -> u32 {
+    x.unwrap()
+}
+
+pub fn boxes() -> Box<u32> {
+    Box::new(1)
+}
+
+pub fn collects(v: &[u32]) -> Vec<u32> {
+    v.iter().copied().collect()
+}
+
+pub fn converts(s: &str) -> String {
+    s.into()
+}
+
+pub fn borrows(x: u32) -> u32 {
+    x
+}
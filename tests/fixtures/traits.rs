@@ -0,0 +1,36 @@
+// Calls to trait methods, with known and unknown receivers.
+
+pub trait Shape {
+    fn area(&self) -> u32;
+}
+
+pub struct Square(pub u32);
+pub struct Circle;
+
+impl Shape for Square {
+    fn area(&self) -> u32 {
+        self.0 * self.0
+    }
+}
+
+impl Shape for Circle {
+    fn area(&self) -> u32 {
+        3
+    }
+}
+
+pub fn known(square: &Square) -> u32 {
+    square.area()
+}
+
+pub fn path(square: &Square) -> u32 {
+    Shape::area(square)
+}
+
+pub fn dynamic(shape: &dyn Shape) -> u32 {
+    shape.area()
+}
+
+pub fn generic<S: Shape>(shape: &S) -> u32 {
+    shape.area()
+}
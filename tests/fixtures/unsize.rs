@@ -0,0 +1,39 @@
+// Trait objects behind pointers other than references and boxes.
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+pub trait Shape {
+    fn area(&self) -> u32;
+}
+
+struct Square;
+struct Circle;
+struct Dot;
+
+impl Shape for Square {
+    fn area(&self) -> u32 { 4 }
+}
+
+impl Shape for Circle {
+    fn area(&self) -> u32 { 3 }
+}
+
+impl Shape for Dot {
+    fn area(&self) -> u32 { 0 }
+}
+
+pub fn rc() -> u32 {
+    let shape: Rc<dyn Shape> = Rc::new(Square);
+    shape.area()
+}
+
+pub fn arc() -> u32 {
+    let shape: Arc<dyn Shape> = Arc::new(Circle);
+    shape.area()
+}
+
+pub fn raw() -> u32 {
+    let shape: *const dyn Shape = &Dot as *const Dot;
+    unsafe { (*shape).area() }
+}
@@ -0,0 +1,7 @@
+fn main() {
+    println!("{}", run());
+}
+
+fn run() -> u32 {
+    dep::helper()
+}